@@ -4,13 +4,20 @@ use arboard::Clipboard;
 use existing_instance::Listener;
 use {
     crate::{
-        conv::{IntpMap, romaji_to_kana},
-        kana::{HIRAGANA, KATAKANA},
+        conv::{Intp, IntpMap, KanaRomajiTable, word_kana},
+        jmdict_index::EntryId,
+        kana::{RomanizationScheme, is_katakana},
         kanji::KanjiDb,
-        segment::InputSpan,
+        learn::{LearnKey, LearnedChoices},
+        output::OutputFormat,
+        segment::{InputSpan, SegmentWarning},
         ui::{DictUiState, KanjiUiState, input::InputUiAction},
+        undo::UndoStack,
+        word_split::{SplitCache, SplitKey},
+        worker::{CancelFlag, Job},
     },
     egui_colors::Colorix,
+    std::{cmp::Reverse, collections::HashMap, sync::Arc},
 };
 
 pub struct HistoryEntry {
@@ -37,9 +44,14 @@ pub struct AppState {
     /// Keeps track whether selected segment changed
     pub last_selected_segment: usize,
     pub cached_suggestions: CachedSuggestions,
-    /// Selected dictionary suggestion (index into cache)
+    /// Looks up the suggestions for `cached_suggestions` in the background
+    pub suggestion_job: Job<(String, String), CachedSuggestions>,
+    /// Selected dictionary suggestion (index into cache).
+    /// Past the dictionary suggestions, it goes on into the completions.
     pub selected_suggestion: Option<usize>,
     pub segments: Vec<InputSpan>,
+    /// The romaji buffer `segments` were made from, used to track interpretations across edits
+    pub segmented_buf: String,
     pub input_ui_action: Option<InputUiAction>,
     /// For some reason the egui memory fails me in getting the scroll offset, so we store it here
     /// Used for synchronizing output scroll and input (romaji) scroll
@@ -48,25 +60,83 @@ pub struct AppState {
     pub ipc_listener: Listener,
     pub colorix: Option<Colorix>,
     pub history: Vec<HistoryEntry>,
-    pub ids_kanji_data: ids_rust::ServerData,
+    /// Shared with the background search in the kanji UI
+    pub ids_kanji_data: Arc<ids_rust::ServerData>,
+    pub romanization: RomanizationScheme,
+    /// Format of the copied output
+    pub output_format: OutputFormat,
+    /// Text being edited for a custom interpretation in the output context menu
+    pub custom_intp_buf: String,
+    /// Split unspaced romaji into dictionary words
+    pub auto_split: bool,
+    pub split_cache: SplitCache,
+    /// Finds split points for the words missing from `split_cache`
+    pub split_job: Job<Vec<SplitKey>, Vec<(SplitKey, Vec<usize>)>>,
+    /// Words of the last segmentation that are missing from `split_cache`
+    pub unsplit_words: Vec<SplitKey>,
+    /// Problems with the input text, shown in the input UI
+    pub segment_warnings: Vec<SegmentWarning>,
+    pub undo_stack: UndoStack,
+    /// Dictionary words picked for readings, used to rank suggestions
+    pub learned: LearnedChoices,
+    /// Words the user picked for segments since the text was last copied, which get learned
+    pub picked_words: HashMap<usize, EntryId>,
+    /// Put the learned choice on a word when it's done being typed
+    pub auto_apply_learned: bool,
 }
 
 #[derive(Default)]
 pub struct CachedSuggestions {
     pub jmdict: Vec<CachedJmdictSuggestion>,
+    /// Longer words the segment could be the start of
+    pub completions: Vec<Completion>,
 }
 
 pub struct CachedJmdictSuggestion {
     pub entry: jmdict::Entry,
     pub mugo_root: Option<mugo::Root>,
+    /// The reading it was matched to, if it only matched approximately (a likely typo)
+    pub correction: Option<&'static str>,
+}
+
+/// A word that the typed text is the start of
+pub struct Completion {
+    pub entry: jmdict::Entry,
+    /// The reading that was matched
+    pub reading: &'static str,
 }
 
 impl CachedSuggestions {
     fn clear(&mut self) {
         self.jmdict.clear();
+        self.completions.clear();
+    }
+    /// Where the entry is in the suggestions, if it's there
+    pub fn position(&self, entry: EntryId) -> Option<usize> {
+        self.jmdict.iter().position(|sugg| sugg.id() == entry)
     }
 }
 
+impl CachedJmdictSuggestion {
+    pub fn id(&self) -> EntryId {
+        EntryId::of(&self.entry)
+    }
+}
+
+/// The conjugation kind choices are learned for
+fn root_kind(root: Option<&mugo::Root>) -> Option<String> {
+    root.map(|root| crate::learn::kind_code(&root.kind).to_owned())
+}
+
+/// What identifies a segment when comparing segmentations: its kind and its text
+fn span_key<'a>(span: &InputSpan, text: &'a str) -> (std::mem::Discriminant<InputSpan>, &'a str) {
+    let (InputSpan::RomajiPunct { start, end }
+    | InputSpan::RomajiWord { start, end }
+    | InputSpan::KanaWord { start, end }
+    | InputSpan::Other { start, end }) = *span;
+    (std::mem::discriminant(span), &text[start..end])
+}
+
 pub enum UiState {
     Input,
     Dict,
@@ -74,10 +144,14 @@ pub enum UiState {
     Help,
     About,
     Theme,
+    Learned,
 }
 
 impl AppState {
     pub fn new(#[cfg(feature = "ipc")] ipc_listener: Listener) -> anyhow::Result<Self> {
+        crate::config::load_user_rules();
+        // Build the dictionary index now rather than on the first keystroke
+        crate::jmdict_index::get();
         Ok(Self {
             intp: IntpMap::default(),
             romaji_buf: String::new(),
@@ -94,56 +168,334 @@ impl AppState {
             last_segs_len: 0,
             last_selected_segment: 0,
             cached_suggestions: CachedSuggestions::default(),
+            suggestion_job: Job::default(),
             selected_suggestion: None,
             segments: Vec::new(),
+            segmented_buf: String::new(),
             input_ui_action: None,
             out_scroll_last_offset: 0.0,
             #[cfg(feature = "ipc")]
             ipc_listener,
             colorix: None,
             history: Vec::new(),
-            ids_kanji_data: ids_rust::init()?,
+            ids_kanji_data: Arc::new(ids_rust::init()?),
+            romanization: RomanizationScheme::default(),
+            output_format: OutputFormat::default(),
+            custom_intp_buf: String::new(),
+            auto_split: false,
+            split_cache: SplitCache::default(),
+            split_job: Job::default(),
+            unsplit_words: Vec::new(),
+            segment_warnings: Vec::new(),
+            undo_stack: UndoStack::default(),
+            learned: LearnedChoices::load(),
+            picked_words: HashMap::new(),
+            auto_apply_learned: false,
         })
     }
-    /// Populate the suggestion cache with entries for the selected segment
-    pub(crate) fn repopulate_suggestion_cache(&mut self) {
+    /// Starts looking up suggestions for the selected segment, which end up in the cache
+    /// once they're found (see [`Self::poll_suggestions`])
+    pub(crate) fn repopulate_suggestion_cache(&mut self, ctx: &egui::Context) {
         self.cached_suggestions.clear();
-        let i = self.selected_segment;
-        let Some(&InputSpan::RomajiWord { start, end }) = self.segments.get(i) else {
+        let Some(key) = self.selected_segment_kana() else {
+            self.suggestion_job.cancel();
+            return;
+        };
+        let (hiragana, katakana) = key.clone();
+        let scheme = self.romanization;
+        self.suggestion_job.start(key, ctx, move |cancel| {
+            crate::suggest::find_all_suggestions(&hiragana, &katakana, scheme, cancel)
+        });
+    }
+
+    /// Puts the looked up suggestions into the cache, if they're for the selected segment
+    pub(crate) fn poll_suggestions(&mut self) {
+        let Some(key) = self.selected_segment_kana() else {
+            return;
+        };
+        if let Some(mut suggestions) = self.suggestion_job.poll(&key) {
+            // Stable, so the dictionary ranking is kept for the rest
+            suggestions.jmdict.sort_by_key(|sugg| {
+                let learn_key = (key.0.clone(), root_kind(sugg.mugo_root.as_ref()));
+                Reverse(self.learned.count(&learn_key, sugg.id()))
+            });
+            self.cached_suggestions = suggestions;
+            // Cycling through suggestions continues from the current choice
+            if let Some(Intp::Dictionary { entry, .. }) = self.intp.get(&self.selected_segment) {
+                self.selected_suggestion = self.cached_suggestions.position(*entry);
+            }
+        }
+    }
+
+    /// Replaces the selected segment with a completion, and picks the word for it
+    ///
+    /// Returns the position to put the text cursor at.
+    pub(crate) fn accept_completion(&mut self, idx: usize) -> Option<usize> {
+        let completion = self.cached_suggestions.completions.get(idx)?;
+        let (en, reading) = (completion.entry, completion.reading);
+        let (start, end, replacement) = match *self.segments.get(self.selected_segment)? {
+            InputSpan::RomajiWord { start, end } => (
+                start,
+                end,
+                KanaRomajiTable::get(self.romanization).convert(reading),
+            ),
+            InputSpan::KanaWord { start, end } => (start, end, reading.to_owned()),
+            InputSpan::RomajiPunct { .. } | InputSpan::Other { .. } => return None,
+        };
+        self.undo_stack.break_merge(&self.romaji_buf, &self.intp);
+        // The old text's word choice doesn't apply to the completed word, but a style does
+        let style = self
+            .intp
+            .get(&self.selected_segment)
+            .filter(|intp| !matches!(intp, Intp::Dictionary { .. }))
+            .cloned();
+        self.romaji_buf.replace_range(start..end, &replacement);
+        self.update_segments();
+        let new_end = start + replacement.len();
+        if let Some(i) = self
+            .segments
+            .iter()
+            .position(|span| span.is_word() && span.cursor_end_pos() == new_end)
+        {
+            self.selected_segment = i;
+            if en.kanji_elements().next().is_some() {
+                self.intp.insert(
+                    i,
+                    Intp::Dictionary {
+                        entry: EntryId::of(&en),
+                        kanji_idx: 0,
+                        root: None,
+                    },
+                );
+                self.pick_word(i);
+            } else if let Some(style) = style {
+                self.intp.insert(i, style);
+            } else if reading.chars().any(is_katakana) {
+                self.intp.insert(i, Intp::Katakana);
+            }
+        }
+        Some(new_end)
+    }
+
+    /// Marks the dictionary word on the segment as picked by the user, so it gets learned
+    pub(crate) fn pick_word(&mut self, segment: usize) {
+        if let Some(Intp::Dictionary { entry, .. }) = self.intp.get(&segment) {
+            self.picked_words.insert(segment, *entry);
+        }
+    }
+
+    /// Learns the dictionary words the user picked in the text, when it's copied
+    ///
+    /// Words that were put there otherwise (like learned choices) aren't learned again.
+    pub(crate) fn learn_choices(&mut self) {
+        for (i, picked) in std::mem::take(&mut self.picked_words) {
+            // Might have been replaced since
+            let Some(Intp::Dictionary {
+                entry,
+                kanji_idx,
+                root,
+            }) = self.intp.get(&i)
+            else {
+                continue;
+            };
+            if *entry != picked {
+                continue;
+            }
+            let Some((hiragana, _)) = self
+                .segments
+                .get(i)
+                .and_then(|&span| word_kana(span, &self.romaji_buf, self.romanization))
+            else {
+                continue;
+            };
+            let key = (hiragana.trim().to_owned(), root_kind(root.as_ref()));
+            self.learned.record(key, *entry, *kanji_idx);
+        }
+        if let Err(e) = self.learned.save() {
+            eprintln!("Failed to save learned choices: {e}");
+        }
+    }
+
+    /// Puts the most picked dictionary word on the segment, unless it already has an intp
+    pub(crate) fn apply_learned(&mut self, segment: usize) {
+        if self.intp.contains_key(&segment) {
+            return;
+        }
+        let Some((hiragana, _)) = self
+            .segments
+            .get(segment)
+            .and_then(|&span| word_kana(span, &self.romaji_buf, self.romanization))
+        else {
             return;
         };
-        let hiragana = romaji_to_kana(&self.romaji_buf[start..end], &HIRAGANA);
         let hiragana = hiragana.trim();
-        let katakana = romaji_to_kana(&self.romaji_buf[start..end], &KATAKANA);
-        let katakana = katakana.trim();
-        let root = mugo_jmdict::Root::Bare(hiragana);
-        let mugo_roots = mugo::deconjugate(hiragana);
-        self.cached_suggestions.jmdict = jmdict::entries()
-            .filter_map(|en| {
-                if root.matches(&en) {
-                    return Some(CachedJmdictSuggestion {
-                        entry: en,
-                        mugo_root: None,
-                    });
-                } else {
-                    for mugo_root in &mugo_roots {
-                        if mugo_jmdict::Root::Conj(mugo_root).matches(&en) {
-                            return Some(CachedJmdictSuggestion {
-                                entry: en,
-                                mugo_root: Some(mugo_root.clone()),
-                            });
-                        }
-                    }
-                }
-                if mugo_jmdict::Root::Bare(katakana).reading_matches(&en) {
-                    return Some(CachedJmdictSuggestion {
-                        entry: en,
-                        mugo_root: None,
-                    });
-                }
-                None
+        let roots = std::iter::once(None).chain(mugo::deconjugate(hiragana).into_iter().map(Some));
+        let best = roots
+            .filter_map(|root| {
+                let key: LearnKey = (hiragana.to_owned(), root_kind(root.as_ref()));
+                let choice = self.learned.best(&key)?;
+                Some((choice.count, choice.entry, choice.kanji_idx, root))
             })
+            .max_by_key(|(count, ..)| *count);
+        let Some((_, entry, kanji_idx, root)) = best else {
+            return;
+        };
+        // Learned with an older dictionary, where the entry had other kanji
+        if entry
+            .entry()
+            .is_none_or(|en| kanji_idx >= en.kanji_elements().len())
+        {
+            return;
+        }
+        self.intp.insert(
+            segment,
+            Intp::Dictionary {
+                entry,
+                kanji_idx,
+                root,
+            },
+        );
+    }
+
+    /// Hiragana and katakana of the selected segment, which suggestions are looked up for
+    fn selected_segment_kana(&self) -> Option<(String, String)> {
+        let span = *self.segments.get(self.selected_segment)?;
+        let (hiragana, katakana) = word_kana(span, &self.romaji_buf, self.romanization)?;
+        Some((hiragana.trim().to_owned(), katakana.trim().to_owned()))
+    }
+
+    /// Segments the romaji buffer, splitting up unspaced words if enabled
+    ///
+    /// Words are split once their split points are found in the background (see
+    /// [`Self::split_unsplit_words`]), until then they're left as they are.
+    /// Also updates the segmentation warnings.
+    pub(crate) fn segment_input(&mut self) -> Vec<InputSpan> {
+        let (segments, warnings) = crate::segment::segment_with_warnings(&self.romaji_buf);
+        self.segment_warnings = warnings;
+        self.unsplit_words.clear();
+        if !self.auto_split {
+            return segments;
+        }
+        let split = |app: &Self| {
+            crate::word_split::split_words(
+                &app.romaji_buf,
+                &segments,
+                app.romanization,
+                &app.split_cache,
+            )
+        };
+        let (mut split_segments, mut uncached) = split(self);
+        if let Some(found) = self.split_job.poll(&uncached) {
+            self.cache_split_points(found);
+            (split_segments, uncached) = split(self);
+        }
+        self.unsplit_words = uncached;
+        split_segments
+    }
+
+    /// Starts finding the split points of the words the last segmentation couldn't split
+    pub(crate) fn split_unsplit_words(&mut self, ctx: &egui::Context) {
+        if self.unsplit_words.is_empty() || self.split_job.is_for(&self.unsplit_words) {
+            return;
+        }
+        let words = self.unsplit_words.clone();
+        self.split_job.start(words.clone(), ctx, move |cancel| {
+            crate::word_split::find_split_points(&words, cancel)
+        });
+    }
+
+    /// Turns the Japanese text in the romaji buffer into editable romaji, keeping kanji choices
+    pub(crate) fn convert_japanese_to_romaji(&mut self) {
+        let (romaji, intps) = crate::reverse::japanese_to_romaji(
+            &self.romaji_buf,
+            &self.segments,
+            &self.intp,
+            self.romanization,
+        );
+        if self.auto_split {
+            // Split the new words right away instead of in the background, otherwise the
+            // choices would be put on the unsplit words, and dropped once they get split
+            let (segments, _) = crate::segment::segment_with_warnings(&romaji);
+            let (_, uncached) = crate::word_split::split_words(
+                &romaji,
+                &segments,
+                self.romanization,
+                &self.split_cache,
+            );
+            let found = crate::word_split::find_split_points(&uncached, &CancelFlag::default());
+            self.cache_split_points(found);
+        }
+        self.set_romaji(romaji, IntpMap::new());
+        // The choices go on the segments the text is split into
+        self.intp = crate::reverse::intp_map_at_offsets(&self.segments, intps);
+    }
+
+    fn cache_split_points(&mut self, found: Vec<(SplitKey, Vec<usize>)>) {
+        // Every edit adds a new word, don't let the cache grow forever
+        if self.split_cache.len() > 4096 {
+            self.split_cache.clear();
+        }
+        self.split_cache.extend(found);
+    }
+
+    /// Re-segments the romaji buffer, moving interpretations along with their segments
+    ///
+    /// Interpretations of segments whose text changed are dropped.
+    pub(crate) fn update_segments(&mut self) {
+        let new = self.segment_input();
+        let old_keys: Vec<_> = self
+            .segments
+            .iter()
+            .map(|span| span_key(span, &self.segmented_buf))
+            .collect();
+        let new_keys: Vec<_> = new
+            .iter()
+            .map(|span| span_key(span, &self.romaji_buf))
             .collect();
+        crate::detect_edit::update_index_map(&mut self.intp, &old_keys, &new_keys);
+        crate::detect_edit::update_index_map(&mut self.picked_words, &old_keys, &new_keys);
+        self.segments = new;
+        self.segmented_buf.clone_from(&self.romaji_buf);
+    }
+
+    /// Replaces the romaji buffer along with its interpretations (which aren't treated as edited)
+    ///
+    /// The replacement is its own undo step.
+    pub(crate) fn set_romaji(&mut self, romaji: String, intp: IntpMap) {
+        self.undo_stack.break_merge(&self.romaji_buf, &self.intp);
+        self.restore_romaji(romaji, intp);
+    }
+
+    fn restore_romaji(&mut self, romaji: String, intp: IntpMap) {
+        self.romaji_buf = romaji;
+        self.intp = intp;
+        self.picked_words.clear();
+        self.segments = self.segment_input();
+        self.segmented_buf.clone_from(&self.romaji_buf);
+        self.last_segs_len = self.segments.len();
+    }
+
+    /// Goes back to before the last change of the text or the interpretations
+    pub(crate) fn undo(&mut self) {
+        if let Some(state) = self.undo_stack.undo() {
+            self.restore_romaji(state.romaji_buf, state.intp);
+        }
+    }
+
+    pub(crate) fn redo(&mut self) {
+        if let Some(state) = self.undo_stack.redo() {
+            self.restore_romaji(state.romaji_buf, state.intp);
+        }
+    }
+
+    /// Lowercases the romaji buffer
+    ///
+    /// Uppercase romaji can have its own meaning (zeNin), so this is treated like any other
+    /// edit: segments whose text changed lose their interpretations.
+    pub(crate) fn normalize_case(&mut self) {
+        self.undo_stack.break_merge(&self.romaji_buf, &self.intp);
+        self.romaji_buf.make_ascii_lowercase();
+        self.update_segments();
     }
 
     /// The SFML backend uses a more robust clipboard mechanism than what SFML offers (arboard),
@@ -156,3 +508,16 @@ impl AppState {
         ctx.output_mut(|out| out.copied_text = text.to_owned());
     }
 }
+
+#[test]
+fn test_case_change_drops_intp() {
+    let (old, new) = ("zeNin desu", "zenin desu");
+    let old_segments = crate::segment::segment(old);
+    let new_segments = crate::segment::segment(new);
+    let old_keys: Vec<_> = old_segments.iter().map(|s| span_key(s, old)).collect();
+    let new_keys: Vec<_> = new_segments.iter().map(|s| span_key(s, new)).collect();
+    let mut intp = IntpMap::from([(0, Intp::Katakana), (1, Intp::Katakana)]);
+    crate::detect_edit::update_index_map(&mut intp, &old_keys, &new_keys);
+    assert!(!intp.contains_key(&0));
+    assert!(intp.contains_key(&1));
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+/// The directory where simplekanainput looks for user configuration files
+///
+/// Returns `None` if it can't be determined (e.g. on the web).
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("simplekanainput"))
+}
+
+/// Loads the user's romaji -> kana rules (`rules.txt` in the config dir), if present
+pub fn load_user_rules() {
+    let Some(path) = config_dir().map(|dir| dir.join("rules.txt")) else {
+        return;
+    };
+    let src = match std::fs::read_to_string(&path) {
+        Ok(src) => src,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            eprintln!("Failed to read {}: {e}", path.display());
+            return;
+        }
+    };
+    match crate::kana::UserRules::parse(&src) {
+        Ok(rules) => {
+            let _ = crate::kana::USER_RULES.set(rules);
+        }
+        Err(e) => eprintln!("Error in {}: {e}", path.display()),
+    }
+}
@@ -1,24 +1,27 @@
 use {
     crate::{
-        kana::{HIRAGANA, KATAKANA, RomajiKanaTable},
+        jmdict_index::EntryId,
+        kana::{
+            RomajiKanaTable, RomanizationScheme, USER_RULES, UserRules, ascii_to_fullwidth,
+            hiragana_to_katakana, katakana_to_halfwidth, katakana_to_hiragana,
+        },
         kanji::KanjiDb,
         radicals::Radical,
-        segment::InputSpan,
+        segment::{InputSpan, WORD_JOINER, is_escapable, unescape},
     },
-    std::collections::HashMap,
+    std::{collections::HashMap, sync::OnceLock},
 };
 
 #[derive(Debug, Clone)]
 pub enum Intp {
     Hiragana,
     Katakana,
+    /// Half-width katakana (ｶﾀｶﾅ)
+    HalfwidthKatakana,
+    /// The romaji itself, as full-width characters (ＡＢＣ１２３)
+    Fullwidth,
     Dictionary {
-        /// Index into cached suggestions.
-        ///
-        /// Not ideal that the entry and this index can potentially get desynced,
-        /// but jmdict::Entry doesn't really provide a way to identify itself uniquely.
-        cached_sug_idx: usize,
-        en: jmdict::Entry,
+        entry: EntryId,
         kanji_idx: usize,
         root: Option<mugo::Root>,
     },
@@ -26,13 +29,14 @@ pub enum Intp {
         db_idx: usize,
     },
     Radical(Radical),
+    /// Arbitrary text typed in by the user
+    Custom(String),
 }
 
 pub type IntpMap = HashMap<usize, Intp>;
 
-/// Max possible length of a romaji kana "atom".
-/// "sshi" is an example of a romaji kana atom, with length of 4.
-const MAX_ROMAJI_ATOM_LEN: usize = 4;
+#[cfg(test)]
+use crate::kana::{HEPBURN_HIRAGANA as HIRAGANA, HEPBURN_KATAKANA as KATAKANA};
 
 pub fn romaji_to_kana(romaji: &str, table: &RomajiKanaTable) -> String {
     let mut out = String::new();
@@ -43,6 +47,23 @@ pub fn romaji_to_kana(romaji: &str, table: &RomajiKanaTable) -> String {
     out
 }
 
+/// Splits romaji into the atoms it's parsed as, returns (byte range, kana) pairs
+pub fn romaji_atoms<'a>(
+    romaji: &'a str,
+    table: &RomajiKanaTable,
+) -> Vec<(std::ops::Range<usize>, &'a str)> {
+    let mut atoms = Vec::new();
+    let mut parser = RomajiParser::new(romaji);
+    loop {
+        let start = parser.cursor;
+        let Some(kana) = parser.next_largest_match(table) else {
+            break;
+        };
+        atoms.push((start..parser.cursor, kana));
+    }
+    atoms
+}
+
 struct RomajiParser<'a> {
     cursor: usize,
     src: &'a str,
@@ -57,33 +78,84 @@ impl<'a> RomajiParser<'a> {
     /// On match, returns a match from the kana table.
     /// If there are no matches, returns a single character from the source string.
     ///
-    /// At string end, or non-unicode-boundary index, it returns None
+    /// Doubled consonants (other than n) followed by a valid syllable produce a sokuon,
+    /// e.g. "kka" is parsed as "っ" + "か". So is the Hepburn "tch" ("tchi" -> "っち").
+    /// User rules for the doubled consonant itself ("xx") take precedence.
+    ///
+    /// "n'" is always a syllabic "ん" ("kan'i" -> "かんい"). So is "nn", unless it's followed by
+    /// a vowel or y, in which case it's "ん" + "な" etc. ("konnichiha" -> "こんにちは").
+    ///
+    /// Escaped special characters ("\\-") are returned as they are, without the backslash.
+    /// The word joiner ends the current atom, and returns an empty string.
+    ///
+    /// At string end, it returns None
     fn next_largest_match(&mut self, table: &RomajiKanaTable) -> Option<&'a str> {
-        // The maximum end the match can reach (so it doesn't go out of bounds)
-        let max_match_end = std::cmp::min(self.cursor + MAX_ROMAJI_ATOM_LEN, self.src.len());
-        let mut match_len = std::cmp::min(MAX_ROMAJI_ATOM_LEN, max_match_end - self.cursor);
-        while match_len > 0 {
-            let end = self.cursor + match_len;
-            if end > max_match_end {
-                break;
-            }
-            let atom = self.src.get(self.cursor..end)?;
-            match table.lookup(atom) {
-                Some(kana) => {
-                    self.cursor += match_len;
-                    return Some(kana);
-                }
-                None => {
-                    match_len -= 1;
-                }
-            }
+        let rest = self.src.get(self.cursor..)?;
+        if let Some(escaped) = rest.strip_prefix('\\')
+            && let Some(ch) = escaped.chars().next()
+            && is_escapable(ch)
+        {
+            self.cursor += 1 + ch.len_utf8();
+            return Some(&escaped[..ch.len_utf8()]);
+        }
+        if rest.starts_with(WORD_JOINER) {
+            self.cursor += WORD_JOINER.len_utf8();
+            return Some("");
+        }
+        if let Some(len) = explicit_n_len(rest)
+            && let Some((1, kana)) = table.longest_match("n")
+        {
+            self.cursor += len;
+            return Some(kana);
+        }
+        if is_sokuon(rest, table) {
+            self.cursor += 1;
+            return Some(table.sokuon);
         }
-        let ret = self.src.get(self.cursor..=self.cursor);
-        self.cursor += 1;
-        ret
+        if let Some((len, kana)) = table.longest_match(rest) {
+            self.cursor += len;
+            return Some(kana);
+        }
+        let ch_len = rest.chars().next()?.len_utf8();
+        self.cursor += ch_len;
+        Some(&rest[..ch_len])
     }
 }
 
+/// If `src` starts with an explicit syllabic n ("n'" or "nn"), returns its length
+fn explicit_n_len(src: &str) -> Option<usize> {
+    match src.as_bytes() {
+        [b'n', b'\'', ..] => Some(2),
+        [b'n', b'n', b'a' | b'i' | b'u' | b'e' | b'o' | b'y', ..] => None,
+        [b'n', b'n', ..] => Some(2),
+        _ => None,
+    }
+}
+
+/// Whether `src` starts with a doubled consonant that should produce a sokuon
+fn is_sokuon(src: &str, table: &RomajiKanaTable) -> bool {
+    is_sokuon_with(src, table, USER_RULES.get())
+}
+
+/// [`is_sokuon`], with the given user rules.
+/// A user rule for the doubled consonant ("xx") takes precedence.
+pub(crate) fn is_sokuon_with(
+    src: &str,
+    table: &RomajiKanaTable,
+    user_rules: Option<&UserRules>,
+) -> bool {
+    let &[first, second, ..] = src.as_bytes() else {
+        return false;
+    };
+    if user_rules.is_some_and(|rules| rules.match_len(src).is_some_and(|len| len >= 2)) {
+        return false;
+    }
+    let is_consonant =
+        first.is_ascii_lowercase() && !matches!(first, b'a' | b'i' | b'u' | b'e' | b'o' | b'n');
+    (is_consonant && (first == second || (first == b't' && second == b'c')))
+        && table.longest_match(&src[1..]).is_some()
+}
+
 #[test]
 fn test_find_largest_match() {
     let mut parser = RomajiParser::new("...nani?");
@@ -108,45 +180,194 @@ fn test_find_largest_match() {
     assert_eq!(parser.next_largest_match(&HIRAGANA), None);
 }
 
+/// Kana -> romaji conversion table, the inverse of a scheme's romaji -> kana tables.
+///
+/// The romaji it produces converts back to the same kana with [`romaji_to_kana`].
+pub struct KanaRomajiTable {
+    map: HashMap<&'static str, &'static str>,
+    /// Length of the longest kana key, in chars
+    max_kana_len: usize,
+}
+
+impl KanaRomajiTable {
+    /// The table for `scheme`, built on first use
+    pub fn get(scheme: RomanizationScheme) -> &'static Self {
+        static TABLES: [OnceLock<KanaRomajiTable>; RomanizationScheme::ALL.len()] =
+            [const { OnceLock::new() }; RomanizationScheme::ALL.len()];
+        TABLES[scheme as usize].get_or_init(|| Self::new(scheme))
+    }
+    fn new(scheme: RomanizationScheme) -> Self {
+        let mut map = HashMap::new();
+        for (romaji, kana) in scheme.hiragana().pairs().chain(scheme.katakana().pairs()) {
+            // Uppercase shortcuts ("A", "N") would be mangled by case normalization
+            if romaji.bytes().any(|b| b.is_ascii_uppercase()) {
+                continue;
+            }
+            map.entry(kana).or_insert(romaji);
+        }
+        let max_kana_len = map.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+        Self { map, max_kana_len }
+    }
+    /// Finds the longest kana atom that `src` starts with, returns (byte length, romaji)
+    fn longest_match(&self, src: &str) -> Option<(usize, &'static str)> {
+        let ends = src.char_indices().map(|(i, ch)| i + ch.len_utf8());
+        let ends: Vec<usize> = ends.take(self.max_kana_len).collect();
+        ends.into_iter()
+            .rev()
+            .find_map(|end| self.map.get(&src[..end]).map(|romaji| (end, *romaji)))
+    }
+    /// Converts kana to romaji. Characters that aren't known kana are left alone.
+    pub fn convert(&self, kana: &str) -> String {
+        let mut out = String::new();
+        let mut rest = kana;
+        while let Some(ch) = rest.chars().next() {
+            if matches!(ch, 'っ' | 'ッ') {
+                rest = &rest[ch.len_utf8()..];
+                // Double the consonant of the next syllable if possible, see `is_sokuon`
+                match self
+                    .longest_match(rest)
+                    .and_then(|(_, romaji)| romaji.bytes().next())
+                {
+                    Some(b) if b.is_ascii_lowercase() && !b"aiueon".contains(&b) => {
+                        out.push(b as char);
+                    }
+                    _ => out.push_str("xtsu"),
+                }
+                continue;
+            }
+            match self.longest_match(rest) {
+                Some((len, romaji)) => {
+                    rest = &rest[len..];
+                    out.push_str(romaji);
+                    // Make sure a syllabic n isn't read together with the next syllable
+                    if romaji == "n"
+                        && let Some((_, next)) = self.longest_match(rest)
+                        && next.starts_with(['a', 'i', 'u', 'e', 'o', 'y', 'n'])
+                    {
+                        out.push('\'');
+                    }
+                }
+                None => {
+                    out.push(ch);
+                    rest = &rest[ch.len_utf8()..];
+                }
+            }
+        }
+        out
+    }
+}
+
+#[test]
+fn test_kana_to_romaji_round_trip() {
+    for scheme in RomanizationScheme::ALL {
+        let table = KanaRomajiTable::get(scheme);
+        for kana in [
+            "こんにちは",
+            "かんい",
+            "しんや",
+            "ほんや",
+            "がっこう",
+            "まっちゃ",
+            "いっしょに",
+            "ちょっと",
+            "あっ",
+            "みんな",
+            "コーヒー",
+            "パーティー",
+            "ヴァイオリン",
+            "ゐる",
+            "「はい」、そうです。",
+        ] {
+            let romaji = table.convert(kana);
+            let back = if kana.chars().any(crate::kana::is_katakana) {
+                romaji_to_kana(&romaji, scheme.katakana())
+            } else {
+                romaji_to_kana(&romaji, scheme.hiragana())
+            };
+            assert_eq!(back, kana, "{scheme:?}: {romaji}");
+        }
+    }
+    let table = KanaRomajiTable::get(RomanizationScheme::Hepburn);
+    assert_eq!(table.convert("しんや"), "shin'ya");
+    assert_eq!(table.convert("がっこう"), "gakkou");
+}
+
+/// Hiragana and katakana forms of a romaji or kana word, `None` for other segments
+pub fn word_kana(
+    span: InputSpan,
+    text: &str,
+    scheme: RomanizationScheme,
+) -> Option<(String, String)> {
+    match span {
+        InputSpan::RomajiWord { start, end } => {
+            let romaji = &text[start..end];
+            Some((
+                romaji_to_kana(romaji, scheme.hiragana()),
+                romaji_to_kana(romaji, scheme.katakana()),
+            ))
+        }
+        InputSpan::KanaWord { start, end } => {
+            let kana = &text[start..end];
+            Some((katakana_to_hiragana(kana), hiragana_to_katakana(kana)))
+        }
+        InputSpan::RomajiPunct { .. } | InputSpan::Other { .. } => None,
+    }
+}
+
 /// Precondition: If a text atom has an intp, it must have a kanji reading
 pub fn with_input_span_converted_form(
     span: &InputSpan,
     i: usize,
     text: &str,
     intp: &IntpMap,
+    scheme: RomanizationScheme,
     kanji_db: &KanjiDb,
     mut f: impl FnMut(&str),
 ) {
-    let romaji = match *span {
+    let (src, is_kana) = match *span {
         InputSpan::RomajiWord { start, end } | InputSpan::RomajiPunct { start, end } => {
-            &text[start..end]
+            (&text[start..end], false)
+        }
+        InputSpan::KanaWord { start, end } => {
+            // Kana is kept as it was typed, unless it has an interpretation
+            if !intp.contains_key(&i) {
+                f(&text[start..end]);
+                return;
+            }
+            (&text[start..end], true)
         }
         InputSpan::Other { start, end } => {
-            // We don't want to touch non-romaji segments at all
-            f(&text[start..end]);
+            // We don't want to touch non-romaji segments at all, apart from escapes
+            f(&unescape(&text[start..end]));
             return;
         }
     };
+    let to_kana = |katakana| match (is_kana, katakana) {
+        (false, false) => romaji_to_kana(src, scheme.hiragana()),
+        (false, true) => romaji_to_kana(src, scheme.katakana()),
+        (true, false) => katakana_to_hiragana(src),
+        (true, true) => hiragana_to_katakana(src),
+    };
     let intp = intp.get(&i).unwrap_or(&Intp::Hiragana);
     match intp {
-        Intp::Hiragana => {
-            f(&romaji_to_kana(romaji, &HIRAGANA));
-        }
-        Intp::Katakana => {
-            f(&romaji_to_kana(romaji, &KATAKANA));
-        }
+        Intp::Hiragana => f(&to_kana(false)),
+        Intp::Katakana => f(&to_kana(true)),
+        Intp::HalfwidthKatakana => f(&katakana_to_halfwidth(&to_kana(true))),
+        Intp::Fullwidth => f(&ascii_to_fullwidth(src)),
         Intp::Dictionary {
-            cached_sug_idx: _,
-            en,
+            entry,
             kanji_idx,
             root,
         } => {
-            let mut kanji_string = en
-                .kanji_elements()
-                .nth(*kanji_idx)
-                .unwrap()
-                .text
-                .to_string();
+            // The entry or its kanji can be gone after a dictionary update
+            let Some(kanji) = entry
+                .entry()
+                .and_then(|en| en.kanji_elements().nth(*kanji_idx))
+            else {
+                f(&to_kana(false));
+                return;
+            };
+            let mut kanji_string = kanji.text.to_string();
             if let Some(root) = root {
                 if let Some(pos) = kanji_string.rfind(root.dict_suffix()) {
                     kanji_string.truncate(pos);
@@ -162,25 +383,85 @@ pub fn with_input_span_converted_form(
             f(&pair.chars[0].to_string());
         }
         Intp::Kanji { db_idx } => f(kanji_db.kanji[*db_idx].chars[0]),
+        Intp::Custom(text) => f(text),
     }
 }
 
-pub fn to_japanese(
-    text: &str,
-    segments: &[InputSpan],
-    intp: &IntpMap,
-    kanji_db: &KanjiDb,
-) -> String {
-    let mut s = String::new();
-    for (i, span) in segments.iter().enumerate() {
-        with_input_span_converted_form(span, i, text, intp, kanji_db, |conv| {
-            s.push_str(conv);
-        });
-    }
-    s
+#[test]
+fn test_stale_kanji_idx() {
+    let text = "taberu";
+    let (_, en) = crate::jmdict_index::get().lookup("たべる").next().unwrap();
+    let intp = IntpMap::from([(
+        0,
+        Intp::Dictionary {
+            entry: crate::jmdict_index::EntryId::of(&en),
+            kanji_idx: 99,
+            root: None,
+        },
+    )]);
+    let mut out = String::new();
+    with_input_span_converted_form(
+        &crate::segment::segment(text)[0],
+        0,
+        text,
+        &intp,
+        RomanizationScheme::Hepburn,
+        &KanjiDb::load(),
+        |conv| out.push_str(conv),
+    );
+    // Kanji that's not in the entry falls back to the reading
+    assert_eq!(out, "たべる");
 }
 
 #[test]
 fn test_decompose() {
     assert_eq!(romaji_to_kana("sugoi", &HIRAGANA), "すごい");
 }
+
+#[test]
+fn test_sokuon() {
+    assert_eq!(romaji_to_kana("kitte", &HIRAGANA), "きって");
+    assert_eq!(romaji_to_kana("matchi", &HIRAGANA), "まっち");
+    assert_eq!(romaji_to_kana("macchi", &HIRAGANA), "まっち");
+    assert_eq!(romaji_to_kana("happyou", &HIRAGANA), "はっぴょう");
+    assert_eq!(romaji_to_kana("happyou", &KATAKANA), "ハッピョウ");
+    assert_eq!(romaji_to_kana("hhi", &HIRAGANA), "っひ");
+    assert_eq!(romaji_to_kana("bba", &KATAKANA), "ッバ");
+    assert_eq!(romaji_to_kana("zzu", &HIRAGANA), "っず");
+    assert_eq!(romaji_to_kana("rra", &HIRAGANA), "っら");
+    assert_eq!(romaji_to_kana("wwa", &HIRAGANA), "っわ");
+    assert_eq!(romaji_to_kana("issho", &HIRAGANA), "いっしょ");
+    // Doubled n is not a sokuon
+    assert_eq!(romaji_to_kana("konna", &HIRAGANA), "こんな");
+    // Doubled consonant without a valid syllable after it
+    assert_eq!(romaji_to_kana("kk", &HIRAGANA), "kk");
+}
+
+#[test]
+fn test_escapes() {
+    assert_eq!(romaji_to_kana(r"sa\-ba", &HIRAGANA), "さ-ば");
+    assert_eq!(romaji_to_kana("sa-ba", &HIRAGANA), "さーば");
+    assert_eq!(romaji_to_kana(r"hai\.", &HIRAGANA), "はい.");
+    assert_eq!(romaji_to_kana(r"a\_i", &HIRAGANA), "あ_い");
+    assert_eq!(romaji_to_kana(r"a\b", &HIRAGANA), "あ\\b");
+}
+
+#[test]
+fn test_word_joiner() {
+    assert_eq!(romaji_to_kana("ki_wo_tsukeru", &HIRAGANA), "きをつける");
+    assert_eq!(romaji_to_kana("kon_ya", &HIRAGANA), "こんや");
+    assert_eq!(romaji_to_kana("konya", &HIRAGANA), "こにゃ");
+}
+
+#[test]
+fn test_explicit_n() {
+    assert_eq!(romaji_to_kana("kan'i", &HIRAGANA), "かんい");
+    assert_eq!(romaji_to_kana("shin'ya", &HIRAGANA), "しんや");
+    assert_eq!(romaji_to_kana("kani", &HIRAGANA), "かに");
+    assert_eq!(romaji_to_kana("kannji", &HIRAGANA), "かんじ");
+    assert_eq!(romaji_to_kana("hon", &HIRAGANA), "ほん");
+    assert_eq!(romaji_to_kana("honn", &KATAKANA), "ホン");
+    assert_eq!(romaji_to_kana("konnichiha", &HIRAGANA), "こんにちは");
+    assert_eq!(romaji_to_kana("konnnichiha", &HIRAGANA), "こんにちは");
+    assert_eq!(romaji_to_kana("konnyaku", &HIRAGANA), "こんにゃく");
+}
@@ -1,26 +1,63 @@
 use std::collections::HashMap;
 
-/// Detects if `new` inserted or deleted items compared to `old`.
-/// Assumes there is only point of edit. (one cursor)
+/// Finds out which items of `old` are still in `new`, using a longest common subsequence.
+/// Handles any number of insertions, deletions and substitutions.
 ///
-/// Returns (position, extent) where extent is:
-/// - zero if there are no changes
-/// - negative if there were removed items
-/// - positive if there were inserted items
-fn detect_edit<T: PartialEq>(old: &[T], new: &[T]) -> (usize, isize) {
-    let pos = old
+/// Returns the index in `new` for each item of `old`, or `None` if it was removed or changed.
+fn match_items<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Option<usize>> {
+    // Common prefix and suffix are matched right away, that's all there is to most edits
+    let prefix = old
         .iter()
         .zip(new.iter())
         .take_while(|(old, new)| old == new)
         .count();
-    (pos, new.len() as isize - old.len() as isize)
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let mut matches: Vec<Option<usize>> = (0..old.len()).map(|_| None).collect();
+    for (i, m) in matches.iter_mut().enumerate().take(prefix) {
+        *m = Some(i);
+    }
+    for i in 0..suffix {
+        matches[old.len() - 1 - i] = Some(new.len() - 1 - i);
+    }
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    // lcs[i * width + j] is the LCS length of old_mid[i..] and new_mid[j..]
+    let width = new_mid.len() + 1;
+    let mut lcs = vec![0usize; (old_mid.len() + 1) * width];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() && j < new_mid.len() {
+        if old_mid[i] == new_mid[j] {
+            matches[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matches
 }
 
 #[test]
 fn test_no_change() {
     let slice1 = &[0, 1, 2];
     let slice2 = &[0, 1, 2];
-    assert_eq!(detect_edit(slice1, slice2).1, 0);
+    assert_eq!(match_items(slice1, slice2), [Some(0), Some(1), Some(2)]);
 }
 
 #[test]
@@ -28,39 +65,29 @@ fn test_various() {
     let slice1 = &["watashi", "no", "yume"];
     // Insertion of one item at index 2
     let slice2 = &["watashi", "no", "daijina", "yume"];
-    assert_eq!(detect_edit(slice1, slice2), (2, 1));
+    assert_eq!(match_items(slice1, slice2), [Some(0), Some(1), Some(3)]);
     //  Deletion of two items at index 1
     let slice3 = &["watashi", "yume"];
-    assert_eq!(detect_edit(slice2, slice3), (1, -2));
+    assert_eq!(match_items(slice2, slice3), [Some(0), None, None, Some(1)]);
     // Add two items at index 0
     let slice4 = &["kore", "ha", "watashi", "yume"];
-    assert_eq!(detect_edit(slice3, slice4), (0, 2));
-}
-
-fn update_index_map<V>(map: &mut HashMap<usize, V>, pos: usize, extent: isize) {
-    let mut kvpairs: Vec<_> = map.drain().collect();
-    kvpairs.retain_mut(|(k, _)| {
-        let mut retain = true;
-        if *k >= pos {
-            match k.checked_add_signed(extent) {
-                Some(val) => *k = val,
-                None => retain = false,
-            }
-        }
-        retain
-    });
-    *map = HashMap::from_iter(kvpairs);
+    assert_eq!(match_items(slice3, slice4), [Some(2), Some(3)]);
+    // Several edits at once: substitution, deletion, insertion
+    let slice5 = &["sore", "ha", "yume", "desu"];
+    assert_eq!(match_items(slice4, slice5), [None, Some(1), None, Some(2)]);
 }
 
-pub fn detect_edit_update_index_map<T: PartialEq, V>(
-    map: &mut HashMap<usize, V>,
-    old: &[T],
-    new: &[T],
-) {
-    let (pos, extent) = detect_edit(old, new);
-    if extent != 0 {
-        update_index_map(map, pos, extent);
+/// Moves the keys of `map` from indices of `old` items to indices of the same items in `new`.
+/// Entries of items that were removed or changed are dropped.
+pub fn update_index_map<T: PartialEq, V>(map: &mut HashMap<usize, V>, old: &[T], new: &[T]) {
+    if old == new {
+        return;
     }
+    let matches = match_items(old, new);
+    *map = std::mem::take(map)
+        .into_iter()
+        .filter_map(|(k, v)| Some((matches.get(k).copied().flatten()?, v)))
+        .collect();
 }
 
 #[test]
@@ -70,11 +97,25 @@ fn index_tracking_proof_of_concept() {
     let mut attr_map = HashMap::from([(0usize, "私"), (2, "人間")]);
     // Insert 3 tokens at 0
     let tokens2 = tok("hai, sou desu. watashi ha ningen desu.");
-    detect_edit_update_index_map(&mut attr_map, &tokens1, &tokens2);
+    update_index_map(&mut attr_map, &tokens1, &tokens2);
     assert_eq!(attr_map[&3], "私");
     assert_eq!(attr_map[&5], "人間");
     let tokens3 = tok("ningen desu.");
     // Remove 5 tokens at 0
-    detect_edit_update_index_map(&mut attr_map, &tokens2, &tokens3);
+    update_index_map(&mut attr_map, &tokens2, &tokens3);
     assert_eq!(attr_map[&0], "人間");
+    assert_eq!(attr_map.len(), 1);
+    // Edits in two places at once (like a paste replacing a selection)
+    let mut attr_map = HashMap::from([(0usize, "私"), (2, "人間"), (4, "夢")]);
+    let tokens4 = tok("watashi ha ningen no yume");
+    let tokens5 = tok("kore watashi ha ningen yume da");
+    update_index_map(&mut attr_map, &tokens4, &tokens5);
+    assert_eq!(attr_map[&1], "私");
+    assert_eq!(attr_map[&3], "人間");
+    assert_eq!(attr_map[&4], "夢");
+    // A changed item loses its value
+    let tokens6 = tok("kore watashi ha ningyou yume da");
+    update_index_map(&mut attr_map, &tokens5, &tokens6);
+    assert!(!attr_map.contains_key(&3));
+    assert_eq!(attr_map.len(), 2);
 }
@@ -0,0 +1,201 @@
+//! Furigana alignment: which part of a reading belongs to which kanji
+
+use crate::{
+    kana::{is_hiragana, is_katakana, katakana_to_hiragana},
+    kanji::KanjiDb,
+};
+
+/// A piece of text, with its reading if it's not kana
+#[derive(Debug, PartialEq)]
+pub struct FuriganaPart<'a> {
+    pub text: &'a str,
+    pub reading: Option<&'a str>,
+}
+
+/// Aligns `reading` against `text` (e.g. a kanji element and a reading element)
+///
+/// Each kanji gets its own reading if it can be found in the kanji database, otherwise
+/// consecutive kanji share one, like 今日 (きょう).
+/// Returns `None` if the kana in `text` don't fit the reading.
+pub fn align<'a>(
+    text: &'a str,
+    reading: &'a str,
+    kanji_db: &KanjiDb,
+) -> Option<Vec<FuriganaPart<'a>>> {
+    let groups = split_groups(text);
+    let mut group_readings = Vec::new();
+    if !align_groups(&groups, reading, &mut group_readings) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for (group, group_reading) in groups.into_iter().zip(group_readings) {
+        if group.starts_with(is_kana) {
+            parts.push(FuriganaPart {
+                text: group,
+                reading: None,
+            });
+            continue;
+        }
+        let mut split = Vec::new();
+        if group.chars().count() > 1
+            && split_by_kanji(group, group_reading, None, kanji_db, &mut split)
+        {
+            parts.extend(split);
+        } else {
+            parts.push(FuriganaPart {
+                text: group,
+                reading: Some(group_reading),
+            });
+        }
+    }
+    Some(parts)
+}
+
+/// Small ヶ and ヵ are read like kanji (一ヶ月), so they don't count
+fn is_kana(ch: char) -> bool {
+    (is_hiragana(ch) || is_katakana(ch)) && !matches!(ch, 'ヶ' | 'ヵ')
+}
+
+/// Whether two kana strings are the same, ignoring hiragana/katakana differences
+fn same_kana(a: &str, b: &str) -> bool {
+    katakana_to_hiragana(a) == katakana_to_hiragana(b)
+}
+
+/// Splits text into alternating runs of kana and kanji
+fn split_groups(text: &str) -> Vec<&str> {
+    let mut groups = Vec::new();
+    let mut rest = text;
+    while let Some(first) = rest.chars().next() {
+        let len = rest
+            .char_indices()
+            .find(|&(_, ch)| is_kana(ch) != is_kana(first))
+            .map_or(rest.len(), |(i, _)| i);
+        let (group, remainder) = rest.split_at(len);
+        groups.push(group);
+        rest = remainder;
+    }
+    groups
+}
+
+/// Finds the reading of each group, using the kana groups as anchors
+fn align_groups<'a>(groups: &[&str], reading: &'a str, out: &mut Vec<&'a str>) -> bool {
+    let Some((group, rest)) = groups.split_first() else {
+        return reading.is_empty();
+    };
+    let mut ends: Vec<usize> = reading
+        .char_indices()
+        .map(|(i, ch)| i + ch.len_utf8())
+        .collect();
+    if group.starts_with(is_kana) {
+        // Kana have to match exactly
+        ends.retain(|&end| same_kana(&reading[..end], group));
+    }
+    for end in ends {
+        out.push(&reading[..end]);
+        if align_groups(rest, &reading[end..], out) {
+            return true;
+        }
+        out.pop();
+    }
+    false
+}
+
+/// Splits the reading of a kanji group between its kanji, using the kanji database
+fn split_by_kanji<'a>(
+    group: &'a str,
+    reading: &'a str,
+    prev: Option<char>,
+    kanji_db: &KanjiDb,
+    out: &mut Vec<FuriganaPart<'a>>,
+) -> bool {
+    let Some(ch) = group.chars().next() else {
+        return reading.is_empty();
+    };
+    let (text, rest) = group.split_at(ch.len_utf8());
+    // 々 repeats the previous kanji
+    let ch = if ch == '々' { prev } else { Some(ch) };
+    let Some(kanji) = ch.and_then(|ch| kanji_db.find(ch)) else {
+        return false;
+    };
+    for kanji_reading in kanji.readings.iter().flat_map(|r| reading_variants(r)) {
+        let Some(part_reading) = reading.get(..kanji_reading.len()) else {
+            continue;
+        };
+        if !same_kana(part_reading, &kanji_reading) {
+            continue;
+        }
+        out.push(FuriganaPart {
+            text,
+            reading: Some(part_reading),
+        });
+        if split_by_kanji(rest, &reading[part_reading.len()..], ch, kanji_db, out) {
+            return true;
+        }
+        out.pop();
+    }
+    false
+}
+
+/// The ways a kanji database reading can be pronounced inside a word
+///
+/// Readings are like "アク" or "わる-い", where the part after "-" is okurigana.
+/// Besides the reading itself, this includes rendaku (日々 -> ひび) and
+/// sokuon (学校 -> がっこう) forms.
+fn reading_variants(reading: &str) -> Vec<String> {
+    let stem = reading.split('-').next().unwrap_or(reading);
+    let stem = katakana_to_hiragana(stem);
+    let mut variants = vec![stem.clone()];
+    let mut chars = stem.chars();
+    if let Some(first) = chars.next() {
+        let offsets: &[u32] = if "かきくけこさしすせそたちつてと".contains(first) {
+            &[1]
+        } else if "はひふへほ".contains(first) {
+            &[1, 2]
+        } else {
+            &[]
+        };
+        for offset in offsets {
+            if let Some(voiced) = char::from_u32(first as u32 + offset) {
+                variants.push(format!("{voiced}{}", chars.as_str()));
+            }
+        }
+    }
+    for i in 0..variants.len() {
+        if variants[i].chars().count() > 1
+            && let Some(last) = variants[i].pop()
+        {
+            if "つちくき".contains(last) {
+                variants.push(format!("{}っ", variants[i]));
+            }
+            variants[i].push(last);
+        }
+    }
+    variants
+}
+
+#[test]
+fn test_align() {
+    let db = KanjiDb::load();
+    let fmt = |text, reading| {
+        let Some(parts) = align(text, reading, &db) else {
+            return "<none>".to_string();
+        };
+        let mut out = String::new();
+        for part in parts {
+            out.push_str(part.text);
+            if let Some(reading) = part.reading {
+                out.push_str(&format!("({reading})"));
+            }
+        }
+        out
+    };
+    assert_eq!(fmt("食べ物", "たべもの"), "食(た)べ物(もの)");
+    assert_eq!(fmt("学校", "がっこう"), "学(がっ)校(こう)");
+    assert_eq!(fmt("英語", "えいご"), "英(えい)語(ご)");
+    assert_eq!(fmt("今日", "きょう"), "今日(きょう)");
+    assert_eq!(fmt("日々", "ひび"), "日(ひ)々(び)");
+    assert_eq!(fmt("取り入れる", "とりいれる"), "取(と)り入(い)れる");
+    assert_eq!(fmt("一ヶ月", "いっかげつ"), "一ヶ月(いっかげつ)");
+    assert_eq!(fmt("ボール箱", "ボールばこ"), "ボール箱(ばこ)");
+    assert_eq!(fmt("食べる", "のむ"), "<none>");
+}
@@ -0,0 +1,155 @@
+//! JMdict entries indexed by the text of their reading and kanji elements, and by number
+
+use {
+    crate::kana::is_hiragana,
+    std::{collections::HashMap, sync::OnceLock},
+};
+
+pub struct JmdictIndex {
+    /// All entries, in dictionary order
+    entries: Vec<jmdict::Entry>,
+    /// Positions in `entries` by element text, in ascending order
+    by_text: HashMap<&'static str, Vec<u32>>,
+    /// Positions in `entries` by JMdict sequence number
+    by_number: HashMap<u32, u32>,
+    /// Reading element texts with the positions of their entries, sorted for prefix search
+    readings: Vec<(&'static str, u32)>,
+    /// (position, kanji element index) by the kanji element text without trailing okurigana
+    by_stem: HashMap<&'static str, Vec<(u32, usize)>>,
+    /// Length of the longest stem, in chars
+    max_stem_len: usize,
+}
+
+/// Stable identity of a JMdict entry: its sequence number
+///
+/// Unlike positions in a list of entries, it stays valid when the list changes,
+/// and it can be stored.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct EntryId(pub u32);
+
+impl EntryId {
+    pub fn of(en: &jmdict::Entry) -> Self {
+        Self(en.number)
+    }
+    /// Looks up the entry
+    pub fn entry(self) -> Option<jmdict::Entry> {
+        get().by_number(self.0)
+    }
+}
+
+/// The index, built on first use
+pub fn get() -> &'static JmdictIndex {
+    static INDEX: OnceLock<JmdictIndex> = OnceLock::new();
+    INDEX.get_or_init(JmdictIndex::build)
+}
+
+impl JmdictIndex {
+    fn build() -> Self {
+        let entries: Vec<jmdict::Entry> = jmdict::entries().collect();
+        let mut by_text: HashMap<_, Vec<u32>> = HashMap::new();
+        let mut by_number = HashMap::new();
+        let mut readings = Vec::new();
+        let mut by_stem: HashMap<_, Vec<_>> = HashMap::new();
+        let mut max_stem_len = 0;
+        for (pos, en) in entries.iter().enumerate() {
+            let pos = pos as u32;
+            by_number.insert(en.number, pos);
+            for (kanji_idx, elem) in en.kanji_elements().enumerate() {
+                let stem = elem.text.trim_end_matches(is_hiragana);
+                if stem.is_empty() {
+                    continue;
+                }
+                max_stem_len = max_stem_len.max(stem.chars().count());
+                by_stem.entry(stem).or_default().push((pos, kanji_idx));
+            }
+            readings.extend(en.reading_elements().map(|elem| (elem.text, pos)));
+            let texts = en
+                .reading_elements()
+                .map(|elem| elem.text)
+                .chain(en.kanji_elements().map(|elem| elem.text));
+            for text in texts {
+                let positions = by_text.entry(text).or_default();
+                // Elements of the same entry can have the same text
+                if positions.last() != Some(&pos) {
+                    positions.push(pos);
+                }
+            }
+        }
+        readings.sort_unstable();
+        readings.dedup();
+        Self {
+            entries,
+            by_text,
+            by_number,
+            readings,
+            by_stem,
+            max_stem_len,
+        }
+    }
+    /// Entries with a reading or kanji element that's exactly `text`, in dictionary order
+    ///
+    /// Along with each entry comes its position in the dictionary.
+    pub fn lookup(&self, text: &str) -> impl Iterator<Item = (u32, jmdict::Entry)> {
+        self.by_text
+            .get(text)
+            .into_iter()
+            .flatten()
+            .map(|&pos| (pos, self.entries[pos as usize]))
+    }
+    /// Entries a deconjugated word can be a form of, in dictionary order, with their positions
+    ///
+    /// They're looked up by the dictionary form, and by the root itself, because some entries
+    /// don't include the dictionary suffix: nouns taking する (じっと for じっとしている), etc.
+    pub fn lookup_conj(&self, root: &mugo::Root) -> Vec<(u32, jmdict::Entry)> {
+        let dict_form = format!("{}{}", root.text, root.dict_suffix());
+        let mut found: Vec<_> = self
+            .lookup(&dict_form)
+            .chain(self.lookup(&root.text))
+            .filter(|(_, en)| mugo_jmdict::Root::Conj(root).matches(en))
+            .collect();
+        found.sort_by_key(|(pos, _)| *pos);
+        found.dedup_by_key(|(pos, _)| *pos);
+        found
+    }
+    /// Entries with a reading that starts with `prefix`, along with that reading and
+    /// the dictionary position
+    pub fn lookup_prefix(
+        &self,
+        prefix: &str,
+    ) -> impl Iterator<Item = (&'static str, u32, jmdict::Entry)> {
+        let start = self.readings.partition_point(|(text, _)| *text < prefix);
+        self.readings[start..]
+            .iter()
+            .take_while(move |(text, _)| text.starts_with(prefix))
+            .map(|&(text, pos)| (text, pos, self.entries[pos as usize]))
+    }
+    /// All reading element texts, along with the dictionary position and entry
+    pub fn readings(&self) -> impl Iterator<Item = (&'static str, u32, jmdict::Entry)> {
+        self.readings
+            .iter()
+            .map(|&(text, pos)| (text, pos, self.entries[pos as usize]))
+    }
+    /// Kanji elements whose text is `stem` followed by nothing but okurigana,
+    /// as (entry, kanji element index)
+    pub fn lookup_stem(&self, stem: &str) -> impl Iterator<Item = (jmdict::Entry, usize)> {
+        self.by_stem
+            .get(stem)
+            .into_iter()
+            .flatten()
+            .map(|&(pos, kanji_idx)| (self.entries[pos as usize], kanji_idx))
+    }
+    /// Length of the longest stem [`Self::lookup_stem`] can find, in chars
+    pub fn max_stem_len(&self) -> usize {
+        self.max_stem_len
+    }
+    /// The entry with the given JMdict sequence number
+    pub fn by_number(&self, number: u32) -> Option<jmdict::Entry> {
+        self.by_number
+            .get(&number)
+            .map(|&pos| self.entries[pos as usize])
+    }
+    /// Whether any entry has a reading or kanji element that's exactly `text`
+    pub fn contains(&self, text: &str) -> bool {
+        self.by_text.contains_key(text)
+    }
+}
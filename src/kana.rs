@@ -1,44 +1,387 @@
-struct RomajiKanaPair {
-    romaji: &'static str,
-    kana: &'static str,
+use std::sync::OnceLock;
+
+/// A node of a romaji prefix trie, stored in a flat array.
+///
+/// Children are kept as a singly linked list (`first_child` -> `next_sibling` -> ...),
+/// which keeps the nodes small enough to build the whole trie in a const context.
+#[derive(Clone, Copy)]
+struct TrieNode {
+    byte: u8,
+    kana: Option<&'static str>,
+    /// Index of the first child, 0 if there is none (the root is never a child)
+    first_child: u16,
+    /// Index of the next sibling, 0 if there is none
+    next_sibling: u16,
+}
+
+impl TrieNode {
+    const EMPTY: Self = Self {
+        byte: 0,
+        kana: None,
+        first_child: 0,
+        next_sibling: 0,
+    };
+}
+
+/// Upper bound of the number of trie nodes needed for `groups` (root + one node per byte)
+const fn trie_len(groups: &[&[(&str, &str)]]) -> usize {
+    let mut len = 1;
+    let mut g = 0;
+    while g < groups.len() {
+        let mut i = 0;
+        while i < groups[g].len() {
+            len += groups[g][i].0.len();
+            i += 1;
+        }
+        g += 1;
+    }
+    len
+}
+
+/// Builds a romaji prefix trie at compile time from groups of (romaji, kana) pairs.
+///
+/// If a romaji atom appears multiple times, the first occurrence wins.
+/// This allows scheme specific groups to override the common ones by coming first.
+const fn build_trie<const N: usize>(groups: &[&[(&str, &'static str)]]) -> [TrieNode; N] {
+    let mut nodes = [TrieNode::EMPTY; N];
+    let mut used = 1;
+    let mut g = 0;
+    while g < groups.len() {
+        let mut i = 0;
+        while i < groups[g].len() {
+            let (romaji, kana) = groups[g][i];
+            let bytes = romaji.as_bytes();
+            let mut node = 0;
+            let mut j = 0;
+            while j < bytes.len() {
+                // Find the child with the matching byte, or append a new one
+                let mut child = nodes[node].first_child as usize;
+                let mut last_sibling = 0;
+                while child != 0 && nodes[child].byte != bytes[j] {
+                    last_sibling = child;
+                    child = nodes[child].next_sibling as usize;
+                }
+                if child == 0 {
+                    child = used;
+                    used += 1;
+                    nodes[child].byte = bytes[j];
+                    if last_sibling == 0 {
+                        nodes[node].first_child = child as u16;
+                    } else {
+                        nodes[last_sibling].next_sibling = child as u16;
+                    }
+                }
+                node = child;
+                j += 1;
+            }
+            if nodes[node].kana.is_none() {
+                nodes[node].kana = Some(kana);
+            }
+            i += 1;
+        }
+        g += 1;
+    }
+    nodes
 }
 
 pub struct RomajiKanaTable {
-    table: &'static [RomajiKanaPair],
+    trie: &'static [TrieNode],
+    /// The groups the trie was built from, in order of precedence
+    groups: &'static [&'static [(&'static str, &'static str)]],
+    /// Small tsu, used for doubled consonants (sokuon)
+    pub sokuon: &'static str,
+    /// Which column of the user rules applies to this table
+    is_katakana: bool,
 }
 
 impl RomajiKanaTable {
+    /// Returns the child of `node` that continues with `byte`
+    fn child(&self, node: usize, byte: u8) -> Option<usize> {
+        let mut child = self.trie[node].first_child as usize;
+        while child != 0 {
+            if self.trie[child].byte == byte {
+                return Some(child);
+            }
+            child = self.trie[child].next_sibling as usize;
+        }
+        None
+    }
+    /// Exact lookup of a single romaji atom in the built-in table
     pub fn lookup(&self, romaji: &str) -> Option<&'static str> {
-        self.table
+        let mut node = 0;
+        for byte in romaji.bytes() {
+            node = self.child(node, byte)?;
+        }
+        self.trie[node].kana
+    }
+    /// All (romaji, kana) pairs of the built-in table that aren't shadowed by an earlier group
+    pub fn pairs(&self) -> impl Iterator<Item = (&'static str, &'static str)> + '_ {
+        self.groups
             .iter()
-            .find_map(|pair| (pair.romaji == romaji).then_some(pair.kana))
+            .flat_map(|group| group.iter().copied())
+            .filter(|&(romaji, kana)| self.lookup(romaji) == Some(kana))
+    }
+    /// Finds the longest romaji atom that `src` starts with.
+    ///
+    /// Returns the length of the atom in bytes, along with the kana it maps to.
+    ///
+    /// The user rules (if loaded) take precedence over the built-in table.
+    pub fn longest_match(&self, src: &str) -> Option<(usize, &'static str)> {
+        self.longest_match_with(src, USER_RULES.get())
+    }
+    fn longest_match_with(
+        &self,
+        src: &str,
+        user_rules: Option<&'static UserRules>,
+    ) -> Option<(usize, &'static str)> {
+        let mut node = 0;
+        let mut found = None;
+        for (i, byte) in src.bytes().enumerate() {
+            let Some(child) = self.child(node, byte) else {
+                break;
+            };
+            node = child;
+            if let Some(kana) = self.trie[node].kana
+                && !user_rules.is_some_and(|rules| rules.removes(&src[..=i]))
+            {
+                found = Some((i + 1, kana));
+            }
+        }
+        if let Some(rules) = user_rules
+            && let Some((len, kana)) = rules.longest_match(src, self.is_katakana)
+            && found.is_none_or(|(found_len, _)| len >= found_len)
+        {
+            found = Some((len, kana));
+        }
+        found
     }
 }
 
-macro_rules! kana_table {
-    ($($romaji:literal $hiragana:literal $katakana:literal)+) => {
-        pub static HIRAGANA: RomajiKanaTable = RomajiKanaTable { table: &[
-                $(
-                RomajiKanaPair {
-                        romaji: $romaji,
-                        kana: $hiragana
-                },
-                )+
-            ]
+/// User defined rules, loaded at startup. See [`UserRules::parse`].
+pub static USER_RULES: OnceLock<UserRules> = OnceLock::new();
+
+struct UserRule {
+    romaji: String,
+    /// (hiragana, katakana), or `None` if the rule removes a built-in entry
+    kana: Option<(String, String)>,
+}
+
+/// Romaji -> kana rules defined by the user, layered over the built-in tables
+pub struct UserRules {
+    rules: Vec<UserRule>,
+}
+
+impl UserRules {
+    /// Parses a rule file.
+    ///
+    /// Each line is one of
+    /// - `<romaji> <hiragana> [katakana]`: Add or override a rule.
+    ///   If katakana is omitted, it's derived from the hiragana.
+    /// - `<romaji>`: Remove a built-in rule
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(src: &str) -> anyhow::Result<Self> {
+        let mut rules = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let romaji = tokens.next().unwrap_or_default().to_owned();
+            let kana = match (tokens.next(), tokens.next()) {
+                (None, _) => None,
+                (Some(hiragana), katakana) => Some((
+                    hiragana.to_owned(),
+                    katakana.map_or_else(|| hiragana_to_katakana(hiragana), str::to_owned),
+                )),
+            };
+            if tokens.next().is_some() {
+                anyhow::bail!("Line {}: Too many fields: {line}", i + 1);
+            }
+            rules.push(UserRule { romaji, kana });
+        }
+        Ok(Self { rules })
+    }
+    fn removes(&self, romaji: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.kana.is_none() && rule.romaji == romaji)
+    }
+    /// Length of the longest rule that `src` starts with (not counting removals)
+    pub fn match_len(&self, src: &str) -> Option<usize> {
+        self.longest_match(src, false).map(|(len, _)| len)
+    }
+    fn longest_match(&self, src: &str, katakana: bool) -> Option<(usize, &str)> {
+        self.rules
+            .iter()
+            .filter(|rule| src.starts_with(&rule.romaji))
+            .filter_map(|rule| {
+                let (hira, kata) = rule.kana.as_ref()?;
+                Some((
+                    rule.romaji.len(),
+                    if katakana { kata } else { hira }.as_str(),
+                ))
+            })
+            .max_by_key(|(len, _)| *len)
+    }
+}
+
+pub fn is_hiragana(ch: char) -> bool {
+    matches!(ch, 'ぁ'..='ゖ' | 'ゝ' | 'ゞ')
+}
+
+pub fn is_katakana(ch: char) -> bool {
+    matches!(ch, 'ァ'..='ヺ' | 'ー' | 'ヽ' | 'ヾ')
+}
+
+pub fn is_kanji(ch: char) -> bool {
+    matches!(ch, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '々' | '〆')
+}
+
+/// Converts the hiragana in `src` to katakana, leaves everything else alone
+pub fn hiragana_to_katakana(src: &str) -> String {
+    src.chars()
+        .map(|ch| match ch {
+            _ if is_hiragana(ch) => char::from_u32(ch as u32 + 0x60).unwrap_or(ch),
+            _ => ch,
+        })
+        .collect()
+}
+
+/// Converts the katakana in `src` to hiragana, leaves everything else alone
+pub fn katakana_to_hiragana(src: &str) -> String {
+    src.chars()
+        .map(|ch| match ch {
+            'ァ'..='ヶ' => char::from_u32(ch as u32 - 0x60).unwrap_or(ch),
+            _ => ch,
+        })
+        .collect()
+}
+
+/// Full-width kana and punctuation that have a half-width form, in half-width block order
+const HALFWIDTH_SOURCE: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン゛゜";
+
+/// Converts full-width katakana in `src` to half-width katakana, leaves everything else alone
+///
+/// Voiced kana are split into the base kana and a (semi-)voiced sound mark, like ｶﾞ.
+pub fn katakana_to_halfwidth(src: &str) -> String {
+    let mut out = String::new();
+    for ch in src.chars() {
+        let (base, mark) = match ch {
+            'ヴ' => ('ウ', Some('ﾞ')),
+            _ if "ガギグゲゴザジズゼゾダヂヅデドバビブベボ".contains(ch) => {
+                (char::from_u32(ch as u32 - 1).unwrap_or(ch), Some('ﾞ'))
+            }
+            _ if "パピプペポ".contains(ch) => {
+                (char::from_u32(ch as u32 - 2).unwrap_or(ch), Some('ﾟ'))
+            }
+            _ => (ch, None),
         };
-        pub static KATAKANA: RomajiKanaTable = RomajiKanaTable { table: &[
-            $(
-            RomajiKanaPair {
-                    romaji: $romaji,
-                    kana: $katakana
-            },
-            )+
-        ]
+        match HALFWIDTH_SOURCE.chars().position(|c| c == base) {
+            Some(pos) => out.push(char::from_u32(0xFF61 + pos as u32).unwrap_or(base)),
+            None => out.push(base),
+        }
+        out.extend(mark);
+    }
+    out
+}
+
+/// Converts ASCII in `src` to full-width forms (ＡＢＣ１２３), leaves everything else alone
+pub fn ascii_to_fullwidth(src: &str) -> String {
+    src.chars()
+        .map(|ch| match ch {
+            ' ' => '\u{3000}',
+            '!'..='~' => char::from_u32(ch as u32 + 0xFEE0).unwrap_or(ch),
+            _ => ch,
+        })
+        .collect()
+}
+
+/// The romanization system used for romaji -> kana conversion
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum RomanizationScheme {
+    /// shi, chi, tsu, fu, ji, sha...
+    #[default]
+    Hepburn,
+    /// si, ti, tu, hu, zi, sya...
+    Kunrei,
+    /// Like Kunrei, but with di, du, wi, we, kwa...
+    Nihon,
+    /// Word processor (IME) style, accepts all of the above
+    Wapuro,
+}
+
+impl RomanizationScheme {
+    pub const ALL: [Self; 4] = [Self::Hepburn, Self::Kunrei, Self::Nihon, Self::Wapuro];
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Hepburn => "Hepburn",
+            Self::Kunrei => "Kunrei-shiki",
+            Self::Nihon => "Nihon-shiki",
+            Self::Wapuro => "Wāpuro",
+        }
+    }
+    pub fn hiragana(self) -> &'static RomajiKanaTable {
+        match self {
+            Self::Hepburn => &HEPBURN_HIRAGANA,
+            Self::Kunrei => &KUNREI_HIRAGANA,
+            Self::Nihon => &NIHON_HIRAGANA,
+            Self::Wapuro => &WAPURO_HIRAGANA,
+        }
+    }
+    pub fn katakana(self) -> &'static RomajiKanaTable {
+        match self {
+            Self::Hepburn => &HEPBURN_KATAKANA,
+            Self::Kunrei => &KUNREI_KATAKANA,
+            Self::Nihon => &NIHON_KATAKANA,
+            Self::Wapuro => &WAPURO_KATAKANA,
+        }
+    }
+}
+
+/// Defines a group of (romaji, hiragana) and (romaji, katakana) pairs
+macro_rules! kana_rows {
+    (
+        $hiragana_name:ident $katakana_name:ident;
+        $($romaji:literal $hiragana:literal $katakana:literal)+
+    ) => {
+        const $hiragana_name: &[(&str, &str)] = &[$(($romaji, $hiragana),)+];
+        const $katakana_name: &[(&str, &str)] = &[$(($romaji, $katakana),)+];
     };
+}
+
+/// Defines a table from row groups. Earlier groups take precedence.
+macro_rules! kana_table {
+    ($name:ident: $($group:ident)+; sokuon: $sokuon:literal, katakana: $katakana:literal) => {
+        pub static $name: RomajiKanaTable = {
+            const GROUPS: &[&[(&str, &str)]] = &[$($group,)+];
+            const TRIE: [TrieNode; trie_len(GROUPS)] = build_trie(GROUPS);
+            RomajiKanaTable {
+                trie: &TRIE,
+                groups: GROUPS,
+                sokuon: $sokuon,
+                is_katakana: $katakana,
+            }
+        };
     };
 }
 
-kana_table! {
+kana_table!(HEPBURN_HIRAGANA: HEPBURN_EXTRA_H HEPBURN_H EXTENDED_H COMMON_H; sokuon: "っ", katakana: false);
+kana_table!(HEPBURN_KATAKANA: HEPBURN_EXTRA_K HEPBURN_K EXTENDED_K COMMON_K; sokuon: "ッ", katakana: true);
+kana_table!(KUNREI_HIRAGANA: KUNREI_H HEPBURN_H EXTENDED_H COMMON_H; sokuon: "っ", katakana: false);
+kana_table!(KUNREI_KATAKANA: KUNREI_K HEPBURN_K EXTENDED_K COMMON_K; sokuon: "ッ", katakana: true);
+kana_table!(NIHON_HIRAGANA: NIHON_H KUNREI_H HEPBURN_H EXTENDED_H COMMON_H; sokuon: "っ", katakana: false);
+kana_table!(NIHON_KATAKANA: NIHON_K KUNREI_K HEPBURN_K EXTENDED_K COMMON_K; sokuon: "ッ", katakana: true);
+kana_table!(WAPURO_HIRAGANA: WAPURO_H NIHON_H KUNREI_H HEPBURN_H EXTENDED_H COMMON_H; sokuon: "っ", katakana: false);
+kana_table!(WAPURO_KATAKANA: WAPURO_K NIHON_K KUNREI_K HEPBURN_K EXTENDED_K COMMON_K; sokuon: "ッ", katakana: true);
+
+// Doubled consonants (kka, tte, tchi, etc.) are not listed here, they are handled by
+// `conv::RomajiParser`, which emits `sokuon` in front of the base syllable.
+
+// Spellings that are the same in every scheme
+kana_rows! {
+    COMMON_H COMMON_K;
     "-"  "ー" "ー"
     ","  "、" "、"
     "!"  "！" "！"
@@ -57,39 +400,16 @@ kana_table! {
     "bya" "びゃ" "ビャ"
     "byo" "びょ" "ビョ"
     "byu" "びゅ" "ビュ"
-    "ccha" "っちゃ" "ッチャ"
-    "cchi" "っち" "ッチ"
-    "cha" "ちゃ" "チャ"
-    "che" "ちぇ" "チェ"
-    "chi" "ち" "チ"
-    "cho" "ちょ" "チョ"
-    "chu" "ちゅ" "チュ"
     "da" "だ" "ダ"
-    "ddo" "っど" "ッド"
     "de" "で" "デ"
-    "di" "でぃ" "ディ"
     "do" "ど" "ド"
-    "du" "づ" "ヅ"
-    "dzu" "づ" "ヅ"
     "e" "え" "エ"
     "E" "ぇ" "ェ"
-    "fa" "ふぁ" "ファ"
-    "fe" "ふぇ" "フェ"
-    "fi" "ふぃ" "フィ"
-    "fu" "ふ" "フ"
-    "ffa" "っふぁ" "ッファ"
-    "ffi" "っふぃ" "ッフィ"
-    "ffu" "っふ" "ッフ"
     "ga" "が" "ガ"
     "ge" "げ" "ゲ"
-    "gge" "っげ" "ッゲ"
     "gi" "ぎ" "ギ"
     "go" "ご" "ゴ"
     "gu" "ぐ" "グ"
-    "ggu" "っぐ" "ッグ"
-    "gga" "っが" "ッガ"
-    "ggi" "っぎ" "ッギ"
-    "ggo" "っご" "ッゴ"
     "gya" "ぎゃ" "ギャ"
     "gyo" "ぎょ" "ギョ"
     "gyu" "ぎゅ" "ギュ"
@@ -102,27 +422,9 @@ kana_table! {
     "hyu" "ひゅ" "ヒュ"
     "i" "い" "イ"
     "I" "ぃ" "ィ"
-    "ja" "じゃ" "ジャ"
-    "jja" "っじゃ" "ッジャ"
-    "ji" "じ" "ジ"
-    "jji" "っじ" "ッジ"
-    "je" "じぇ" "ジェ"
-    "jje" "っじぇ" "ッジェ"
-    "jo" "じょ" "ジョ"
-    "jjo" "っじょ" "ッジョ"
-    "ju" "じゅ" "ジュ"
-    "jju" "っじゅ" "ッジュ"
     "ka" "か" "カ"
     "ke" "け" "ケ"
     "ki" "き" "キ"
-    "kka" "っか" "ッカ"
-    "kke" "っけ" "ッケ"
-    "kki" "っき" "ッキ"
-    "kko" "っこ" "ッコ"
-    "kku" "っく" "ック"
-    "kkya" "っきゃ" "ッキャ"
-    "kkyo" "っきょ" "ッキョ"
-    "kkyu" "っきゅ" "ッキュ"
     "ko" "こ" "コ"
     "ku" "く" "ク"
     "kya" "きゃ" "キャ"
@@ -131,11 +433,6 @@ kana_table! {
     "ma" "ま" "マ"
     "me" "め" "メ"
     "mi" "み" "ミ"
-    "mma" "っま" "ッマ"
-    "mme" "っめ" "ッメ"
-    "mmi" "っみ" "ッミ"
-    "mmo" "っも" "ッモ"
-    "mmu" "っむ" "ッム"
     "mo" "も" "モ"
     "mu" "む" "ム"
     "mya" "みゃ" "ミャ"
@@ -156,54 +453,33 @@ kana_table! {
     "pe" "ぺ" "ペ"
     "pi" "ぴ" "ピ"
     "po" "ぽ" "ポ"
-    "ppa" "っぱ" "ッパ"
-    "ppi" "っぴ" "ッピ"
-    "ppo" "っぽ" "ッポ"
-    "ppu" "っぷ" "ップ"
     "pu" "ぷ" "プ"
+    "pya" "ぴゃ" "ピャ"
     "pyo" "ぴょ" "ピョ"
-    "ppyo" "っぴょ" "ピョ"
     "pyu" "ぴゅ" "ピュ"
-    "ppyu" "っぴゅ" "ピュ"
     "ra" "ら" "ラ"
     "re" "れ" "レ"
     "ri" "り" "リ"
     "ro" "ろ" "ロ"
     "ru" "る" "ル"
-    "rru" "っる" "ッル" // Non-standard, but add it I guess
     "rya" "りゃ" "リャ"
     "ryo" "りょ" "リョ"
     "ryu" "りゅ" "リュ"
     "sa" "さ" "サ"
     "se" "せ" "セ"
-    "sha" "しゃ" "シャ"
-    "shi" "し" "シ"
-    "sho" "しょ" "ショ"
-    "shu" "しゅ" "シュ"
     "so" "そ" "ソ"
-    "ssa" "っさ" "ッサ"
-    "sse" "っせ" "ッセ"
-    "ssha" "っしゃ" "ッシャ"
-    "sshi" "っし" "ッシ"
-    "ssho" "っしょ" "ッショ"
-    "sshu" "っしゅ" "ッシュ"
-    "sso" "っそ" "ッソ"
-    "ssu" "っす" "ッス"
     "su" "す" "ス"
     "ta" "た" "タ"
     "te" "て" "テ"
-    "ti" "てぃ" "ティ"
-    "tyu" "てゅ" "テュ"
     "to" "と" "ト"
-    "tsu" "つ" "ツ"
-    "tta" "った" "ッタ"
-    "tte" "って" "ッテ"
-    "tto" "っと" "ット"
-    "ttsu" "っつ" "ッツ"
-    "tu" "つ" "ツ"
     "u" "う" "ウ"
     "wa" "わ" "ワ"
+    // Obsolete kana. In katakana, wi and we are more likely to be meant as foreign sounds.
+    "we" "ゑ" "ウェ"
+    "wi" "ゐ" "ウィ"
     "wo" "を" "ヲ"
+    "wye" "ゑ" "ヱ"
+    "wyi" "ゐ" "ヰ"
     "ya" "や" "ヤ"
     "yo" "よ" "ヨ"
     "yu" "ゆ" "ユ"
@@ -211,13 +487,284 @@ kana_table! {
     "ze" "ぜ" "ゼ"
     "zo" "ぞ" "ゾ"
     "zu" "ず" "ズ"
-    "vu" "ゔ" "ヴ"
+    // Small kana, with the x or l prefix
+    "xa" "ぁ" "ァ"
+    "xi" "ぃ" "ィ"
+    "xu" "ぅ" "ゥ"
+    "xe" "ぇ" "ェ"
+    "xo" "ぉ" "ォ"
+    "xya" "ゃ" "ャ"
+    "xyu" "ゅ" "ュ"
+    "xyo" "ょ" "ョ"
+    "xwa" "ゎ" "ヮ"
+    "xka" "ゕ" "ヵ"
+    "xke" "ゖ" "ヶ"
+    "xtsu" "っ" "ッ"
+    "xtu" "っ" "ッ"
+    "la" "ぁ" "ァ"
+    "li" "ぃ" "ィ"
+    "lu" "ぅ" "ゥ"
+    "le" "ぇ" "ェ"
+    "lo" "ぉ" "ォ"
+    "lya" "ゃ" "ャ"
+    "lyu" "ゅ" "ュ"
+    "lyo" "ょ" "ョ"
+    "lwa" "ゎ" "ヮ"
+    "lka" "ゕ" "ヵ"
+    "lke" "ゖ" "ヶ"
+    "ltsu" "っ" "ッ"
+    "ltu" "っ" "ッ"
+}
+
+// Extended kana for foreign sounds, following the 外来語の表記 guidelines
+kana_rows! {
+    EXTENDED_H EXTENDED_K;
+    "che" "ちぇ" "チェ"
+    "dhi" "でぃ" "ディ"
+    "dhu" "でゅ" "デュ"
+    "dwu" "どぅ" "ドゥ"
+    "fa" "ふぁ" "ファ"
+    "fe" "ふぇ" "フェ"
+    "fi" "ふぃ" "フィ"
+    "fo" "ふぉ" "フォ"
+    "fyu" "ふゅ" "フュ"
+    "gwa" "ぐぁ" "グァ"
+    "gwe" "ぐぇ" "グェ"
+    "gwi" "ぐぃ" "グィ"
+    "gwo" "ぐぉ" "グォ"
+    "je" "じぇ" "ジェ"
+    "kwa" "くぁ" "クァ"
+    "kwe" "くぇ" "クェ"
+    "kwi" "くぃ" "クィ"
+    "kwo" "くぉ" "クォ"
+    "she" "しぇ" "シェ"
+    "thi" "てぃ" "ティ"
+    "thu" "てゅ" "テュ"
+    "tsa" "つぁ" "ツァ"
+    "tse" "つぇ" "ツェ"
+    "tsi" "つぃ" "ツィ"
+    "tso" "つぉ" "ツォ"
+    "twu" "とぅ" "トゥ"
+    "va" "ゔぁ" "ヴァ"
+    "ve" "ゔぇ" "ヴェ"
     "vi" "ゔぃ" "ヴィ"
     "vo" "ゔぉ" "ヴォ"
+    "vu" "ゔ" "ヴ"
+    "vyu" "ゔゅ" "ヴュ"
+    "wha" "うぁ" "ウァ"
+    "whe" "うぇ" "ウェ"
+    "whi" "うぃ" "ウィ"
+    "who" "うぉ" "ウォ"
+    "ye" "いぇ" "イェ"
+}
+
+// Hepburn spellings. The other schemes accept these too, as most IMEs do.
+kana_rows! {
+    HEPBURN_H HEPBURN_K;
+    "cha" "ちゃ" "チャ"
+    "chi" "ち" "チ"
+    "cho" "ちょ" "チョ"
+    "chu" "ちゅ" "チュ"
+    "fu" "ふ" "フ"
+    "ja" "じゃ" "ジャ"
+    "ji" "じ" "ジ"
+    "jo" "じょ" "ジョ"
+    "ju" "じゅ" "ジュ"
+    "sha" "しゃ" "シャ"
+    "shi" "し" "シ"
+    "sho" "しょ" "ショ"
+    "shu" "しゅ" "シュ"
+    "tsu" "つ" "ツ"
+}
+
+// Spellings that only make sense in Hepburn, because other schemes use them for
+// the "t" and "d" rows
+kana_rows! {
+    HEPBURN_EXTRA_H HEPBURN_EXTRA_K;
+    "di" "でぃ" "ディ"
+    "dzu" "づ" "ヅ"
+    "du" "づ" "ヅ"
+    "dyu" "でゅ" "デュ"
+    "ti" "てぃ" "ティ"
+    "tu" "つ" "ツ"
+    "tyu" "てゅ" "テュ"
+}
+
+// Kunrei-shiki spellings
+kana_rows! {
+    KUNREI_H KUNREI_K;
+    "hu" "ふ" "フ"
+    "si" "し" "シ"
+    "sya" "しゃ" "シャ"
+    "syo" "しょ" "ショ"
+    "syu" "しゅ" "シュ"
+    "ti" "ち" "チ"
+    "tu" "つ" "ツ"
+    "tya" "ちゃ" "チャ"
+    "tyo" "ちょ" "チョ"
+    "tyu" "ちゅ" "チュ"
+    "zi" "じ" "ジ"
+    "zya" "じゃ" "ジャ"
+    "zyo" "じょ" "ジョ"
+    "zyu" "じゅ" "ジュ"
+}
+
+// Nihon-shiki spellings that differ from Kunrei-shiki
+kana_rows! {
+    NIHON_H NIHON_K;
+    "di" "ぢ" "ヂ"
+    "du" "づ" "ヅ"
+    "dya" "ぢゃ" "ヂャ"
+    "dyo" "ぢょ" "ヂョ"
+    "dyu" "ぢゅ" "ヂュ"
+    "gwa" "ぐゎ" "グヮ"
+    "kwa" "くゎ" "クヮ"
+    "we" "ゑ" "ヱ"
+    "wi" "ゐ" "ヰ"
+}
+
+// Wāpuro (IME) spellings, for sounds that are spelled differently in the other schemes
+kana_rows! {
+    WAPURO_H WAPURO_K;
+    "dzu" "づ" "ヅ"
+    "gwa" "ぐぁ" "グァ"
+    "kwa" "くぁ" "クァ"
+    "we" "うぇ" "ウェ"
+    "wi" "うぃ" "ウィ"
 }
 
 // ぃぅぇぉゃゅゎゕゖ゛゜ゝゞゟ ゔ
-// ゐ = wi (obsolete kana)
-// ゑ = we (obsolete kana)
 
 // ッィゥェォャュョヰヱヴヵヶヷヸヹヺ・ーヽヾヮ
+
+#[test]
+fn test_trie_lookup() {
+    let (hiragana, katakana) = (&HEPBURN_HIRAGANA, &HEPBURN_KATAKANA);
+    assert_eq!(hiragana.lookup("shi"), Some("し"));
+    assert_eq!(katakana.lookup("shi"), Some("シ"));
+    assert_eq!(hiragana.lookup("sh"), None);
+    assert_eq!(hiragana.lookup(""), None);
+    assert_eq!(hiragana.longest_match("kyoto"), Some((3, "きょ")));
+    assert_eq!(hiragana.longest_match("nya"), Some((3, "にゃ")));
+    assert_eq!(hiragana.longest_match("nk"), Some((1, "ん")));
+    assert_eq!(hiragana.longest_match("xyz"), None);
+}
+
+#[test]
+fn test_schemes() {
+    use RomanizationScheme as S;
+    let lookup = |scheme: S, romaji| scheme.hiragana().lookup(romaji);
+    assert_eq!(lookup(S::Hepburn, "ti"), Some("てぃ"));
+    assert_eq!(lookup(S::Hepburn, "si"), None);
+    assert_eq!(lookup(S::Kunrei, "ti"), Some("ち"));
+    assert_eq!(lookup(S::Kunrei, "tyu"), Some("ちゅ"));
+    assert_eq!(lookup(S::Kunrei, "shi"), Some("し"));
+    assert_eq!(lookup(S::Kunrei, "di"), None);
+    assert_eq!(lookup(S::Nihon, "di"), Some("ぢ"));
+    assert_eq!(lookup(S::Nihon, "wi"), Some("ゐ"));
+    assert_eq!(lookup(S::Wapuro, "wi"), Some("うぃ"));
+    assert_eq!(lookup(S::Wapuro, "wyi"), Some("ゐ"));
+    assert_eq!(S::Wapuro.katakana().lookup("wi"), Some("ウィ"));
+    assert_eq!(lookup(S::Wapuro, "du"), Some("づ"));
+    assert_eq!(lookup(S::Wapuro, "thi"), Some("てぃ"));
+    assert_eq!(lookup(S::Wapuro, "zya"), Some("じゃ"));
+    assert_eq!(S::Nihon.katakana().lookup("kwa"), Some("クヮ"));
+}
+
+#[test]
+fn test_user_rules() {
+    let rules = UserRules::parse(
+        "# My rules
+        nn ん
+        xx っ ッ
+        kyoto きょうと キョウト
+        ti",
+    )
+    .unwrap();
+    let rules = Box::leak(Box::new(rules));
+    let hiragana = &HEPBURN_HIRAGANA;
+    let katakana = &HEPBURN_KATAKANA;
+    assert_eq!(
+        hiragana.longest_match_with("nna", Some(rules)),
+        Some((2, "ん"))
+    );
+    assert_eq!(
+        katakana.longest_match_with("nna", Some(rules)),
+        Some((2, "ン"))
+    );
+    assert_eq!(
+        katakana.longest_match_with("xx", Some(rules)),
+        Some((2, "ッ"))
+    );
+    assert_eq!(
+        hiragana.longest_match_with("kyoto", Some(rules)),
+        Some((5, "きょうと"))
+    );
+    assert_eq!(hiragana.longest_match_with("ti", Some(rules)), None);
+    // Rules override the sokuon derived from doubled consonants
+    assert!(!crate::conv::is_sokuon_with("xxa", hiragana, Some(rules)));
+    assert!(crate::conv::is_sokuon_with("xxa", hiragana, None));
+    assert!(crate::conv::is_sokuon_with("kka", hiragana, Some(rules)));
+    assert_eq!(
+        hiragana.longest_match_with("ta", Some(rules)),
+        Some((2, "た"))
+    );
+    assert!(UserRules::parse("a b c d").is_err());
+}
+
+#[test]
+fn test_small_kana() {
+    let (hiragana, katakana) = (&HEPBURN_HIRAGANA, &HEPBURN_KATAKANA);
+    assert_eq!(hiragana.lookup("xa"), Some("ぁ"));
+    assert_eq!(hiragana.lookup("lyo"), Some("ょ"));
+    assert_eq!(hiragana.lookup("xtsu"), Some("っ"));
+    assert_eq!(katakana.lookup("ltu"), Some("ッ"));
+    assert_eq!(katakana.lookup("xke"), Some("ヶ"));
+    assert_eq!(hiragana.lookup("wi"), Some("ゐ"));
+    assert_eq!(katakana.lookup("we"), Some("ウェ"));
+    assert_eq!(katakana.lookup("wye"), Some("ヱ"));
+}
+
+#[test]
+fn test_extended_katakana() {
+    let convert = |scheme: RomanizationScheme, romaji: &str| {
+        let table = scheme.katakana();
+        let mut out = String::new();
+        let mut rest = romaji;
+        while let Some((len, kana)) = table.longest_match(rest) {
+            out.push_str(kana);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty(), "{romaji}: {rest} left over");
+        out
+    };
+    use RomanizationScheme as S;
+    assert_eq!(convert(S::Hepburn, "vaiorin"), "ヴァイオリン");
+    assert_eq!(convert(S::Hepburn, "pa-ti-"), "パーティー");
+    assert_eq!(convert(S::Wapuro, "pa-thi-"), "パーティー");
+    assert_eq!(convert(S::Hepburn, "fyu-jon"), "フュージョン");
+    assert_eq!(convert(S::Hepburn, "tsuxa-"), "ツァー");
+    assert_eq!(convert(S::Hepburn, "tsa-"), "ツァー");
+    assert_eq!(convert(S::Hepburn, "tu-"), "ツー");
+    assert_eq!(convert(S::Hepburn, "twu-"), "トゥー");
+    assert_eq!(convert(S::Kunrei, "tu-"), "ツー");
+    assert_eq!(convert(S::Kunrei, "twu-"), "トゥー");
+    assert_eq!(convert(S::Hepburn, "dyu-ku"), "デューク");
+    assert_eq!(convert(S::Nihon, "dwu"), "ドゥ");
+    assert_eq!(convert(S::Hepburn, "kwaruteto"), "クァルテト");
+    assert_eq!(convert(S::Hepburn, "sheru"), "シェル");
+    assert_eq!(convert(S::Hepburn, "whisuki-"), "ウィスキー");
+    assert_eq!(convert(S::Hepburn, "yeru"), "イェル");
+}
+
+#[test]
+fn test_width_conversion() {
+    assert_eq!(katakana_to_halfwidth("カタカナ"), "ｶﾀｶﾅ");
+    assert_eq!(
+        katakana_to_halfwidth("ガッコウ、パーティー。"),
+        "ｶﾞｯｺｳ､ﾊﾟｰﾃｨｰ｡"
+    );
+    assert_eq!(katakana_to_halfwidth("「ヴ」ン"), "｢ｳﾞ｣ﾝ");
+    assert_eq!(katakana_to_halfwidth("ひらがな"), "ひらがな");
+    assert_eq!(ascii_to_fullwidth("ABC123 x!"), "ＡＢＣ１２３　ｘ！");
+}
@@ -29,4 +29,12 @@ impl KanjiDb {
             });
         }
     }
+    /// Finds a kanji by its character, or its old form
+    pub fn find(&self, ch: char) -> Option<&Kanji> {
+        let mut buf = [0; 4];
+        let ch: &str = ch.encode_utf8(&mut buf);
+        self.kanji
+            .iter()
+            .find(|kanji| kanji.chars[0] == ch || kanji.chars[1] == ch)
+    }
 }
@@ -0,0 +1,164 @@
+//! Remembers which dictionary words the user picks for a reading
+
+use {
+    crate::jmdict_index::EntryId,
+    mugo_jmdict::RootKindExt as _,
+    std::{cmp::Reverse, collections::BTreeMap},
+};
+
+/// Stored in the config dir
+const FILE_NAME: &str = "learned.tsv";
+
+/// What choices are learned for: the reading as typed, and the [`kind_code`] of the
+/// conjugated word (`None` if it's not conjugated)
+pub type LearnKey = (String, Option<String>);
+
+/// Identifies the kind of a conjugated word in the learned choices file
+///
+/// Uses the JMdict part of speech code (like `v5k`), which doesn't change when mugo's
+/// labels get reworded.
+pub fn kind_code(kind: &mugo::RootKind) -> &'static str {
+    jmdict::Enum::code(&kind.to_jmdict_part_of_speech())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LearnedChoice {
+    pub entry: EntryId,
+    pub kanji_idx: usize,
+    /// How many times it was copied
+    pub count: u32,
+}
+
+#[derive(Default)]
+pub struct LearnedChoices {
+    /// Choices for each key, most picked first
+    pub map: BTreeMap<LearnKey, Vec<LearnedChoice>>,
+}
+
+impl LearnedChoices {
+    /// Loads the learned choices from the config dir, if there are any
+    pub fn load() -> Self {
+        let Some(path) = crate::config::config_dir().map(|dir| dir.join(FILE_NAME)) else {
+            return Self::default();
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(src) => Self::parse(&src),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                eprintln!("Failed to read {}: {e}", path.display());
+                Self::default()
+            }
+        }
+    }
+    /// Saves the learned choices to the config dir (does nothing if there's none)
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(dir) = crate::config::config_dir() else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(FILE_NAME), self.serialize())?;
+        Ok(())
+    }
+    fn parse(src: &str) -> Self {
+        let mut learned = Self::default();
+        for line in src.lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let &[reading, kind, entry, kanji_idx, count] = fields.as_slice() else {
+                eprintln!("Skipping bad line in {FILE_NAME}: {line}");
+                continue;
+            };
+            let (Ok(number), Ok(kanji_idx), Ok(count)) =
+                (entry.parse(), kanji_idx.parse(), count.parse())
+            else {
+                eprintln!("Skipping bad line in {FILE_NAME}: {line}");
+                continue;
+            };
+            let kind = (!kind.is_empty()).then(|| kind.to_owned());
+            let choices = learned.map.entry((reading.to_owned(), kind)).or_default();
+            choices.push(LearnedChoice {
+                entry: EntryId(number),
+                kanji_idx,
+                count,
+            });
+            choices.sort_by_key(|choice| Reverse(choice.count));
+        }
+        learned
+    }
+    fn serialize(&self) -> String {
+        let mut out = String::from("# reading\tconjugation\tentry\tkanji index\tcount\n");
+        for ((reading, kind), choices) in &self.map {
+            for choice in choices {
+                out.push_str(&format!(
+                    "{reading}\t{}\t{}\t{}\t{}\n",
+                    kind.as_deref().unwrap_or(""),
+                    choice.entry.0,
+                    choice.kanji_idx,
+                    choice.count
+                ));
+            }
+        }
+        out
+    }
+    /// Records that the user went with this choice
+    pub fn record(&mut self, key: LearnKey, entry: EntryId, kanji_idx: usize) {
+        let choices = self.map.entry(key).or_default();
+        match choices
+            .iter_mut()
+            .find(|choice| choice.entry == entry && choice.kanji_idx == kanji_idx)
+        {
+            Some(choice) => choice.count += 1,
+            None => choices.push(LearnedChoice {
+                entry,
+                kanji_idx,
+                count: 1,
+            }),
+        }
+        choices.sort_by_key(|choice| Reverse(choice.count));
+    }
+    /// How many times the entry was picked for the key, with any kanji
+    pub fn count(&self, key: &LearnKey, entry: EntryId) -> u32 {
+        self.map.get(key).map_or(0, |choices| {
+            choices
+                .iter()
+                .filter(|choice| choice.entry == entry)
+                .map(|choice| choice.count)
+                .sum()
+        })
+    }
+    /// The most picked choice for the key
+    pub fn best(&self, key: &LearnKey) -> Option<&LearnedChoice> {
+        self.map.get(key)?.first()
+    }
+    pub fn forget(&mut self, key: &LearnKey, entry: EntryId, kanji_idx: usize) {
+        if let Some(choices) = self.map.get_mut(key) {
+            choices.retain(|choice| choice.entry != entry || choice.kanji_idx != kanji_idx);
+            if choices.is_empty() {
+                self.map.remove(key);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_learned_choices() {
+    let mut learned = LearnedChoices::default();
+    let kaku = ("かく".to_owned(), None);
+    let kaita = ("かいた".to_owned(), Some("v5k".to_owned()));
+    learned.record(kaku.clone(), EntryId(1), 0);
+    learned.record(kaku.clone(), EntryId(2), 0);
+    learned.record(kaku.clone(), EntryId(2), 0);
+    learned.record(kaita.clone(), EntryId(2), 1);
+    assert_eq!(
+        learned.best(&kaku).map(|choice| choice.entry),
+        Some(EntryId(2))
+    );
+    assert_eq!(learned.count(&kaku, EntryId(2)), 2);
+    assert_eq!(learned.count(&kaita, EntryId(1)), 0);
+    let reloaded = LearnedChoices::parse(&learned.serialize());
+    assert_eq!(reloaded.map, learned.map);
+    learned.forget(&kaita, EntryId(2), 1);
+    assert!(!learned.map.contains_key(&kaita));
+}
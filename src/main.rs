@@ -5,13 +5,23 @@ use existing_instance::{Endpoint, Msg};
 use {appstate::AppState, std::sync::Arc};
 
 mod appstate;
+mod config;
 mod conv;
 mod detect_edit;
+mod furigana;
+mod jmdict_index;
 mod kana;
 mod kanji;
+mod learn;
+mod output;
 mod radicals;
+mod reverse;
 mod segment;
+mod suggest;
 mod ui;
+mod undo;
+mod word_split;
+mod worker;
 
 #[cfg(feature = "backend-eframe")]
 mod eframe;
@@ -0,0 +1,226 @@
+//! Output formats for copying, with furigana for kanji segments
+
+use crate::{
+    conv::{Intp, IntpMap, with_input_span_converted_form, word_kana},
+    furigana,
+    kana::RomanizationScheme,
+    kanji::KanjiDb,
+    segment::InputSpan,
+};
+
+/// How the Japanese output is formatted when copied
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OutputFormat {
+    /// Just the Japanese text
+    #[default]
+    Plain,
+    /// `<ruby>漢字<rt>かんじ</rt></ruby>`
+    Html,
+    /// `｜漢字《かんじ》`
+    Aozora,
+    /// `\ruby{漢字}{かんじ}`
+    Latex,
+    /// `漢字[かんじ]`, as used by Anki
+    Bracket,
+}
+
+impl OutputFormat {
+    pub const ALL: [Self; 5] = [
+        Self::Plain,
+        Self::Html,
+        Self::Aozora,
+        Self::Latex,
+        Self::Bracket,
+    ];
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Plain => "Plain text",
+            Self::Html => "HTML ruby",
+            Self::Aozora => "Aozora Bunko",
+            Self::Latex => "LaTeX",
+            Self::Bracket => "Brackets (Anki)",
+        }
+    }
+    fn push_plain(self, out: &mut String, text: &str) {
+        match self {
+            Self::Html => {
+                for ch in text.chars() {
+                    match ch {
+                        '&' => out.push_str("&amp;"),
+                        '<' => out.push_str("&lt;"),
+                        '>' => out.push_str("&gt;"),
+                        _ => out.push(ch),
+                    }
+                }
+            }
+            Self::Aozora => {
+                // Aozora Bunko's notation for the characters it uses itself
+                for ch in text.chars() {
+                    match ch {
+                        '《' => out.push_str("※［＃始め二重山括弧、1-1-52］"),
+                        '》' => out.push_str("※［＃終わり二重山括弧、1-1-53］"),
+                        '｜' => out.push_str("※［＃縦線、1-1-35］"),
+                        _ => out.push(ch),
+                    }
+                }
+            }
+            Self::Latex => {
+                for ch in text.chars() {
+                    match ch {
+                        '\\' => out.push_str("\\textbackslash{}"),
+                        '^' => out.push_str("\\textasciicircum{}"),
+                        '~' => out.push_str("\\textasciitilde{}"),
+                        '{' | '}' | '%' | '&' | '#' | '_' | '$' => {
+                            out.push('\\');
+                            out.push(ch);
+                        }
+                        _ => out.push(ch),
+                    }
+                }
+            }
+            Self::Plain | Self::Bracket => out.push_str(text),
+        }
+    }
+    fn push_ruby(self, out: &mut String, base: &str, reading: &str) {
+        match self {
+            Self::Plain => out.push_str(base),
+            Self::Html => {
+                out.push_str("<ruby>");
+                self.push_plain(out, base);
+                out.push_str("<rt>");
+                self.push_plain(out, reading);
+                out.push_str("</rt></ruby>");
+            }
+            Self::Aozora => {
+                out.push('｜');
+                self.push_plain(out, base);
+                out.push('《');
+                self.push_plain(out, reading);
+                out.push('》');
+            }
+            Self::Latex => {
+                out.push_str("\\ruby{");
+                self.push_plain(out, base);
+                out.push_str("}{");
+                self.push_plain(out, reading);
+                out.push('}');
+            }
+            Self::Bracket => {
+                // Anki needs a space to know where the annotated text starts
+                if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+                    out.push(' ');
+                }
+                out.push_str(base);
+                out.push('[');
+                out.push_str(reading);
+                out.push(']');
+            }
+        }
+    }
+}
+
+/// Converts the text to Japanese in the given format
+///
+/// Kanji segments get the reading they were typed with as furigana.
+pub fn to_japanese(
+    text: &str,
+    segments: &[InputSpan],
+    intp: &IntpMap,
+    scheme: RomanizationScheme,
+    kanji_db: &KanjiDb,
+    format: OutputFormat,
+) -> String {
+    let mut out = String::new();
+    for (i, span) in segments.iter().enumerate() {
+        let reading = match intp.get(&i) {
+            Some(Intp::Dictionary { .. } | Intp::Kanji { .. }) => {
+                word_kana(*span, text, scheme).map(|(hiragana, _)| hiragana)
+            }
+            _ => None,
+        };
+        with_input_span_converted_form(
+            span,
+            i,
+            text,
+            intp,
+            scheme,
+            kanji_db,
+            |conv| match &reading {
+                Some(reading) => push_with_furigana(&mut out, conv, reading, kanji_db, format),
+                None => format.push_plain(&mut out, conv),
+            },
+        );
+    }
+    out
+}
+
+/// Pushes `word` with a reading, annotating each kanji separately where possible
+fn push_with_furigana(
+    out: &mut String,
+    word: &str,
+    reading: &str,
+    kanji_db: &KanjiDb,
+    format: OutputFormat,
+) {
+    let Some(parts) = furigana::align(word, reading, kanji_db) else {
+        format.push_ruby(out, word, reading);
+        return;
+    };
+    for part in parts {
+        match part.reading {
+            Some(reading) => format.push_ruby(out, part.text, reading),
+            None => format.push_plain(out, part.text),
+        }
+    }
+}
+
+#[test]
+fn test_push_with_furigana() {
+    let db = KanjiDb::load();
+    let fmt = |word, reading, format| {
+        let mut out = String::from("は");
+        push_with_furigana(&mut out, word, reading, &db, format);
+        out
+    };
+    assert_eq!(
+        fmt("漢字", "かんじ", OutputFormat::Html),
+        "は<ruby>漢<rt>かん</rt></ruby><ruby>字<rt>じ</rt></ruby>"
+    );
+    assert_eq!(
+        fmt("食べる", "たべる", OutputFormat::Aozora),
+        "は｜食《た》べる"
+    );
+    assert_eq!(
+        fmt("食べる", "たべる", OutputFormat::Latex),
+        "は\\ruby{食}{た}べる"
+    );
+    assert_eq!(
+        fmt("食べる", "たべる", OutputFormat::Bracket),
+        "は 食[た]べる"
+    );
+    assert_eq!(fmt("食べる", "たべる", OutputFormat::Plain), "は食べる");
+    assert_eq!(
+        fmt("食べ物", "たべもの", OutputFormat::Bracket),
+        "は 食[た]べ 物[もの]"
+    );
+    assert_eq!(fmt("りんご", "りんご", OutputFormat::Bracket), "はりんご");
+}
+
+#[test]
+fn test_push_plain_escapes() {
+    let fmt = |text, format: OutputFormat| {
+        let mut out = String::new();
+        format.push_plain(&mut out, text);
+        out
+    };
+    assert_eq!(fmt("<b>&", OutputFormat::Html), "&lt;b&gt;&amp;");
+    assert_eq!(
+        fmt("100% {a_b} #1 \\", OutputFormat::Latex),
+        "100\\% \\{a\\_b\\} \\#1 \\textbackslash{}"
+    );
+    assert_eq!(
+        fmt("｜本《ほん》", OutputFormat::Aozora),
+        "※［＃縦線、1-1-35］本※［＃始め二重山括弧、1-1-52］ほん※［＃終わり二重山括弧、1-1-53］"
+    );
+    assert_eq!(fmt("{a}", OutputFormat::Plain), "{a}");
+}
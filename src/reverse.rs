@@ -0,0 +1,369 @@
+//! Reverse conversion: Japanese text -> romaji + interpretations
+
+use crate::{
+    conv::{Intp, IntpMap, KanaRomajiTable},
+    jmdict_index::EntryId,
+    kana::{RomanizationScheme, is_hiragana, is_kanji, is_katakana},
+    segment::{InputSpan, escape},
+};
+
+/// Converts the Japanese text in the non-romaji segments of `text` into romaji.
+///
+/// Returns the new romaji text, with the interpretations that reproduce the original
+/// Japanese text (including kanji choices, as far as they can be found in the dictionary).
+/// They're keyed by the byte offset of their segment, see [`intp_map_at_offsets`].
+/// Explicit `{}` segments are left alone.
+/// Spaces in converted segments are treated as word separators.
+pub fn japanese_to_romaji(
+    text: &str,
+    segments: &[InputSpan],
+    intp: &IntpMap,
+    scheme: RomanizationScheme,
+) -> (String, Vec<(usize, Intp)>) {
+    let mut conv = ReverseConverter {
+        kana_table: KanaRomajiTable::get(scheme),
+        out: String::new(),
+        intps: Vec::new(),
+    };
+    let mut last_end = 0;
+    // Consecutive Japanese segments are converted together, so words can span them (食べる)
+    let mut pending: Option<(usize, usize)> = None;
+    for (i, span) in segments.iter().enumerate() {
+        let (InputSpan::RomajiWord { start, end }
+        | InputSpan::RomajiPunct { start, end }
+        | InputSpan::KanaWord { start, end }
+        | InputSpan::Other { start, end }) = *span;
+        let src = &text[start..end];
+        let is_explicit = start > 0 && text.as_bytes()[start - 1] == b'{';
+        let convertible = match span {
+            InputSpan::KanaWord { .. } => !intp.contains_key(&i),
+            InputSpan::Other { .. } => !is_explicit && src.chars().any(is_japanese),
+            InputSpan::RomajiWord { .. } | InputSpan::RomajiPunct { .. } => false,
+        };
+        if convertible
+            && let Some((_, pending_end)) = &mut pending
+            && *pending_end == start
+        {
+            *pending_end = end;
+            last_end = end;
+            continue;
+        }
+        if let Some((run_start, run_end)) = pending.take() {
+            conv.convert_run(&text[run_start..run_end], &text[run_end..]);
+        }
+        conv.out.push_str(&text[last_end..start]);
+        last_end = end;
+        if convertible {
+            pending = Some((start, end));
+        } else if let InputSpan::KanaWord { .. } = span
+            && let Some(intp) = intp.get(&i)
+        {
+            // Kana with a kanji choice or other interpretation keeps it
+            conv.push_separator();
+            conv.intps.push((conv.out.len(), intp.clone()));
+            let romaji = conv.kana_table.convert(src);
+            conv.out.push_str(&romaji);
+            conv.push_trailing_space(&text[end..]);
+        } else {
+            if let Some(intp) = intp.get(&i) {
+                conv.intps.push((conv.out.len(), intp.clone()));
+            }
+            conv.out.push_str(src);
+        }
+    }
+    if let Some((run_start, run_end)) = pending {
+        conv.convert_run(&text[run_start..run_end], &text[run_end..]);
+    }
+    let ReverseConverter { mut out, intps, .. } = conv;
+    out.push_str(&text[last_end..]);
+    (out, intps)
+}
+
+/// Puts interpretations keyed by byte offset on the segments starting there
+///
+/// `segments` must be the segmentation the text will be used with, which can be split
+/// further than the plain segmentation.
+pub fn intp_map_at_offsets(segments: &[InputSpan], intps: Vec<(usize, Intp)>) -> IntpMap {
+    let mut map = IntpMap::new();
+    for (offset, intp) in intps {
+        if let Some(idx) = segments.iter().position(|seg| {
+            let (InputSpan::RomajiWord { start, .. }
+            | InputSpan::RomajiPunct { start, .. }
+            | InputSpan::KanaWord { start, .. }
+            | InputSpan::Other { start, .. }) = *seg;
+            start == offset
+        }) {
+            map.insert(idx, intp);
+        }
+    }
+    map
+}
+
+struct ReverseConverter {
+    kana_table: &'static KanaRomajiTable,
+    out: String,
+    /// (byte offset of the segment in `out`, intp)
+    intps: Vec<(usize, Intp)>,
+}
+
+impl ReverseConverter {
+    /// Keeps each converted piece a separate segment
+    fn push_separator(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with(' ') {
+            self.out.push(' ');
+        }
+    }
+    fn push_trailing_space(&mut self, following: &str) {
+        if following.starts_with(|ch: char| ch != ' ') {
+            self.out.push(' ');
+        }
+    }
+    /// Converts a run of Japanese text, `following` is the text after it
+    fn convert_run(&mut self, src: &str, following: &str) {
+        for piece in split_japanese(src) {
+            match piece {
+                Piece::Kana { kana, katakana } => {
+                    self.push_separator();
+                    if katakana {
+                        self.intps.push((self.out.len(), Intp::Katakana));
+                    }
+                    let romaji = self.kana_table.convert(kana);
+                    self.out.push_str(&romaji);
+                }
+                Piece::Dictionary { reading, intp } => {
+                    self.push_separator();
+                    self.intps.push((self.out.len(), intp));
+                    let romaji = self.kana_table.convert(&reading);
+                    self.out.push_str(&romaji);
+                }
+                // Spaces are just separators, which the romaji already has
+                Piece::Other(text) if text.trim_matches(' ').is_empty() => {}
+                Piece::Other(text) => {
+                    self.push_separator();
+                    self.out.push('{');
+                    self.out.push_str(&escape(text));
+                    self.out.push('}');
+                }
+            }
+        }
+        self.push_trailing_space(following);
+    }
+}
+
+fn is_japanese(ch: char) -> bool {
+    is_hiragana(ch) || is_katakana(ch) || is_kanji(ch) || "、。「」！？…".contains(ch)
+}
+
+enum Piece<'a> {
+    Kana { kana: &'a str, katakana: bool },
+    Dictionary { reading: String, intp: Intp },
+    Other(&'a str),
+}
+
+/// Finds the longest dictionary word (possibly conjugated) that `src` starts with.
+///
+/// Returns the byte length of the match, the reading, and the intp.
+fn longest_match(src: &str) -> Option<(usize, String, Intp)> {
+    let index = crate::jmdict_index::get();
+    let stem_ends: Vec<usize> = src
+        .char_indices()
+        .map(|(i, ch)| i + ch.len_utf8())
+        .take(index.max_stem_len())
+        .collect();
+    for &stem_end in stem_ends.iter().rev() {
+        let after_stem = &src[stem_end..];
+        let okurigana_len = after_stem
+            .char_indices()
+            .find(|(_, ch)| !is_hiragana(*ch))
+            .map_or(after_stem.len(), |(i, _)| i);
+        let okurigana = &after_stem[..okurigana_len];
+        let mut best: Option<(usize, String, Intp)> = None;
+        for (en, kanji_idx) in index.lookup_stem(&src[..stem_end]) {
+            if let Some((len, reading, root)) = match_okurigana(en, kanji_idx, okurigana)
+                && best.as_ref().is_none_or(|(best_len, ..)| len > *best_len)
+            {
+                let intp = Intp::Dictionary {
+                    entry: EntryId::of(&en),
+                    kanji_idx,
+                    root,
+                };
+                best = Some((len, reading, intp));
+            }
+        }
+        if let Some((len, reading, intp)) = best {
+            return Some((stem_end + len, reading, intp));
+        }
+    }
+    None
+}
+
+/// Tries to match the kanji element's okurigana (possibly conjugated) against the start of
+/// `okurigana`.
+///
+/// Returns the matched okurigana byte length, the full reading, and the deconjugation root.
+fn match_okurigana(
+    en: jmdict::Entry,
+    kanji_idx: usize,
+    okurigana: &str,
+) -> Option<(usize, String, Option<mugo::Root>)> {
+    let kanji_text = en.kanji_elements().nth(kanji_idx)?.text;
+    let dict_okurigana = &kanji_text[kanji_text.trim_end_matches(is_hiragana).len()..];
+    let reading = en
+        .reading_elements()
+        .map(|elem| elem.text)
+        .find(|text| text.ends_with(dict_okurigana))?;
+    let stem_reading = &reading[..reading.len() - dict_okurigana.len()];
+    let ends = okurigana
+        .char_indices()
+        .map(|(i, ch)| i + ch.len_utf8())
+        .collect::<Vec<_>>();
+    for &end in ends.iter().rev() {
+        let candidate = format!("{stem_reading}{}", &okurigana[..end]);
+        for root in mugo::deconjugate(&candidate) {
+            if mugo_jmdict::Root::Conj(&root).matches(&en) {
+                return Some((end, candidate, Some(root)));
+            }
+        }
+    }
+    okurigana
+        .starts_with(dict_okurigana)
+        .then(|| (dict_okurigana.len(), reading.to_owned(), None))
+}
+
+/// Splits Japanese text into kana runs, dictionary words, and everything else
+fn split_japanese(src: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = src;
+    while let Some(ch) = rest.chars().next() {
+        if is_kanji(ch)
+            && let Some((len, reading, intp)) = longest_match(rest)
+        {
+            pieces.push(Piece::Dictionary { reading, intp });
+            rest = &rest[len..];
+            continue;
+        }
+        let class = CharClass::of(ch);
+        // Kanji without a dictionary match is kept as-is, one at a time
+        let len = if class == CharClass::Kanji {
+            ch.len_utf8()
+        } else {
+            rest.char_indices()
+                .find(|&(_, ch)| {
+                    CharClass::of(ch) != class
+                    // Prolonged sound marks continue hiragana runs too
+                    && !(ch == 'ー' && class == CharClass::Hiragana)
+                })
+                .map_or(rest.len(), |(i, _)| i)
+        };
+        let (run, remainder) = rest.split_at(len);
+        rest = remainder;
+        pieces.push(match class {
+            CharClass::Hiragana | CharClass::Punct => Piece::Kana {
+                kana: run,
+                katakana: false,
+            },
+            CharClass::Katakana => Piece::Kana {
+                kana: run,
+                katakana: true,
+            },
+            CharClass::Kanji | CharClass::Other => Piece::Other(run),
+        });
+    }
+    pieces
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum CharClass {
+    Hiragana,
+    Katakana,
+    Kanji,
+    Punct,
+    Other,
+}
+
+impl CharClass {
+    fn of(ch: char) -> Self {
+        if is_hiragana(ch) {
+            Self::Hiragana
+        } else if is_katakana(ch) {
+            Self::Katakana
+        } else if is_kanji(ch) {
+            Self::Kanji
+        } else if is_japanese(ch) {
+            Self::Punct
+        } else {
+            Self::Other
+        }
+    }
+}
+
+#[test]
+fn test_kana_japanese_to_romaji() {
+    let text = "watashi ha カタカナ です、ひらがな{ひらがな}";
+    let segments = crate::segment::segment(text);
+    let intp = IntpMap::from([(1, Intp::Katakana)]);
+    let (romaji, intps) = japanese_to_romaji(text, &segments, &intp, RomanizationScheme::Hepburn);
+    assert_eq!(romaji, "watashi ha katakana desu , hiragana {ひらがな}");
+    let new_segments = crate::segment::segment(&romaji);
+    let intp = intp_map_at_offsets(&new_segments, intps);
+    assert_eq!(new_segments.len(), 7);
+    assert!(matches!(intp[&1], Intp::Katakana));
+    assert!(matches!(intp[&2], Intp::Katakana));
+    assert_eq!(intp.len(), 2);
+    let out = crate::output::to_japanese(
+        &romaji,
+        &new_segments,
+        &intp,
+        RomanizationScheme::Hepburn,
+        &crate::kanji::KanjiDb::load(),
+        crate::output::OutputFormat::Plain,
+    );
+    assert_eq!(out, "わたしハカタカナです、ひらがなひらがな");
+}
+
+#[test]
+fn test_dictionary_japanese_to_romaji() {
+    let text = "kore ha 食べる";
+    let segments = crate::segment::segment(text);
+    let (romaji, intps) = japanese_to_romaji(
+        text,
+        &segments,
+        &IntpMap::new(),
+        RomanizationScheme::Hepburn,
+    );
+    assert_eq!(romaji, "kore ha taberu");
+    let intp = intp_map_at_offsets(&crate::segment::segment(&romaji), intps);
+    assert_eq!(intp.len(), 1);
+    let Intp::Dictionary {
+        entry, kanji_idx, ..
+    } = &intp[&2]
+    else {
+        panic!("expected a dictionary interpretation");
+    };
+    let en = entry.entry().unwrap();
+    assert_eq!(en.kanji_elements().nth(*kanji_idx).unwrap().text, "食べる");
+}
+
+#[test]
+fn test_japanese_to_romaji_auto_split() {
+    use crate::word_split::{SplitCache, find_split_points, split_words};
+    let text = "わたしは学生です";
+    let scheme = RomanizationScheme::Hepburn;
+    let (romaji, intps) = japanese_to_romaji(
+        text,
+        &crate::segment::segment(text),
+        &IntpMap::new(),
+        scheme,
+    );
+    assert_eq!(romaji, "watashiha gakusei desu");
+    // Split the way the app does with automatic word splitting
+    let segments = crate::segment::segment(&romaji);
+    let mut cache = SplitCache::default();
+    let (_, uncached) = split_words(&romaji, &segments, scheme, &cache);
+    cache.extend(find_split_points(&uncached, &Default::default()));
+    let (segments, _) = split_words(&romaji, &segments, scheme, &cache);
+    assert_eq!(segments.len(), 4);
+    let intp = intp_map_at_offsets(&segments, intps);
+    assert_eq!(intp.len(), 1);
+    assert!(matches!(intp[&2], Intp::Dictionary { .. }));
+}
@@ -1,8 +1,86 @@
+use crate::kana::{is_hiragana, is_katakana};
+
+/// Joins romaji words into one segment, so expressions can be looked up as a whole
+pub const WORD_JOINER: char = '_';
+
+/// Characters with a special meaning, which can be escaped with a backslash (`\{`, `\-`)
+pub fn is_escapable(ch: char) -> bool {
+    matches!(
+        ch,
+        '{' | '}' | '[' | ']' | '-' | '.' | ',' | '!' | '?' | '\\' | '\'' | WORD_JOINER
+    )
+}
+
+/// Removes the backslashes of escaped characters
+pub fn unescape(text: &str) -> std::borrow::Cow<'_, str> {
+    if !text.contains('\\') {
+        return text.into();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '\\'
+            && let Some(&next) = chars.peek()
+            && is_escapable(next)
+        {
+            out.push(next);
+            chars.next();
+        } else {
+            out.push(ch);
+        }
+    }
+    out.into()
+}
+
+/// Escapes the characters in `text` that would otherwise be special
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if is_escapable(ch) {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+    out
+}
+
+/// Problems with the input text that the user should know about
+#[derive(Debug, PartialEq)]
+pub enum SegmentWarning {
+    /// A `{` without a matching `}`
+    UnterminatedBrace,
+    /// A `}` without a matching `{`
+    UnmatchedCloseBrace,
+}
+
+impl SegmentWarning {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::UnterminatedBrace => "Unterminated {, the rest of the text is kept as is",
+            Self::UnmatchedCloseBrace => "} without a matching {, use \\} for a literal }",
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum InputSpan {
-    RomajiWord { start: usize, end: usize },
-    RomajiPunct { start: usize, end: usize },
-    Other { start: usize, end: usize },
+    RomajiWord {
+        start: usize,
+        end: usize,
+    },
+    RomajiPunct {
+        start: usize,
+        end: usize,
+    },
+    /// Hiragana or katakana that's not romaji, but can still have an interpretation
+    KanaWord {
+        start: usize,
+        end: usize,
+    },
+    Other {
+        start: usize,
+        end: usize,
+    },
 }
 
 impl InputSpan {
@@ -10,7 +88,8 @@ impl InputSpan {
         match self {
             InputSpan::RomajiWord { start, end }
             | InputSpan::Other { start, end }
-            | InputSpan::RomajiPunct { start, end } => end - start,
+            | InputSpan::RomajiPunct { start, end }
+            | InputSpan::KanaWord { start, end } => end - start,
         }
     }
 
@@ -21,6 +100,7 @@ impl InputSpan {
         match self {
             InputSpan::RomajiWord { end, .. }
             | InputSpan::RomajiPunct { end, .. }
+            | InputSpan::KanaWord { end, .. }
             | InputSpan::Other { end, .. } => end,
         }
     }
@@ -30,7 +110,8 @@ impl InputSpan {
         match self {
             InputSpan::RomajiWord { start, end }
             | InputSpan::Other { start, end }
-            | InputSpan::RomajiPunct { start, end } => &str[start..end],
+            | InputSpan::RomajiPunct { start, end }
+            | InputSpan::KanaWord { start, end } => &str[start..end],
         }
     }
 
@@ -39,130 +120,137 @@ impl InputSpan {
         match self {
             InputSpan::RomajiWord { start, end }
             | InputSpan::Other { start, end }
-            | InputSpan::RomajiPunct { start, end } => (start..=end).contains(&cursor),
+            | InputSpan::RomajiPunct { start, end }
+            | InputSpan::KanaWord { start, end } => (start..=end).contains(&cursor),
         }
     }
 
-    pub(crate) fn is_romaji_word(self) -> bool {
-        matches!(self, InputSpan::RomajiWord { .. })
+    /// Whether this is a word that can get suggestions (romaji or kana)
+    pub(crate) fn is_word(self) -> bool {
+        matches!(
+            self,
+            InputSpan::RomajiWord { .. } | InputSpan::KanaWord { .. }
+        )
     }
 }
 
+#[cfg(test)]
 pub fn segment(input_text: &str) -> Vec<InputSpan> {
+    segment_with_warnings(input_text).0
+}
+
+pub fn segment_with_warnings(input_text: &str) -> (Vec<InputSpan>, Vec<SegmentWarning>) {
+    #[derive(PartialEq, Clone, Copy)]
     enum Status {
         Init,
         // Romaji word
         RomajiWord,
         // Punctuation, segmented separately from word text
         RomajiPunct,
+        // Kana typed in (or pasted) as-is, hiragana and katakana are separate words
+        KanaWord { katakana: bool },
         OtherText,
         ExplicitOther,
     }
 
     let mut segs = Vec::new();
+    let mut warnings = Vec::new();
     let mut status = Status::Init;
     let mut last_segment_begin = 0;
-    for (pos, byte) in input_text.bytes().enumerate() {
-        let is_romaji_word = byte.is_ascii_alphabetic() || matches!(byte, b'-');
-        let is_romaji_punct = matches!(byte, b'.' | b',' | b'!' | b'?' | b'[' | b']');
-        match status {
-            Status::Init => {
-                if is_romaji_word {
-                    status = Status::RomajiWord;
-                } else if byte == b'{' {
-                    status = Status::ExplicitOther;
-                    last_segment_begin = pos + 1;
-                } else if is_romaji_punct {
-                    status = Status::RomajiPunct;
-                } else {
-                    status = Status::OtherText;
-                }
-            }
-            Status::RomajiWord => {
-                if !is_romaji_word {
-                    segs.push(InputSpan::RomajiWord {
-                        start: last_segment_begin,
-                        end: pos,
-                    });
-                    if is_romaji_punct {
-                        status = Status::RomajiPunct;
-                        last_segment_begin = pos;
-                    } else if byte == b'{' {
-                        status = Status::ExplicitOther;
-                        last_segment_begin = pos + 1;
-                    } else {
-                        status = Status::OtherText;
-                        last_segment_begin = pos;
-                    }
-                }
-            }
-            Status::OtherText => {
-                if is_romaji_word | is_romaji_punct {
-                    segs.push(InputSpan::Other {
-                        start: last_segment_begin,
-                        end: pos,
-                    });
-                    if is_romaji_word {
-                        status = Status::RomajiWord;
-                    } else {
-                        status = Status::RomajiPunct;
-                    }
-                    last_segment_begin = pos;
-                } else if byte == b'{' {
-                    segs.push(InputSpan::Other {
-                        start: last_segment_begin,
-                        end: pos,
-                    });
-                    status = Status::ExplicitOther;
-                    last_segment_begin = pos + 1;
-                }
+    // Whether the current char is escaped by the previous backslash
+    let mut escaped = false;
+    for (pos, ch) in input_text.char_indices() {
+        // An escaped char belongs to the same segment as its backslash
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        let is_escape = ch == '\\' && input_text[pos + 1..].starts_with(is_escapable);
+        escaped = is_escape;
+        if status == Status::ExplicitOther {
+            if ch == '}' {
+                segs.push(InputSpan::Other {
+                    start: last_segment_begin,
+                    end: pos,
+                });
+                status = Status::Init;
+                last_segment_begin = pos + 1;
             }
-            Status::ExplicitOther => {
-                if byte == b'}' {
-                    segs.push(InputSpan::Other {
-                        start: last_segment_begin,
-                        end: pos,
-                    });
-                    status = Status::Init;
-                    last_segment_begin = pos + 1;
-                }
+            continue;
+        }
+        // Prolonged sound marks continue kana words of either kind (すごーい)
+        if ch == 'ー' && matches!(status, Status::KanaWord { .. }) {
+            continue;
+        }
+        // An apostrophe after n (explicit syllabic n, like "kan'i") is part of the word
+        let is_explicit_n = ch == '\'' && input_text[..pos].ends_with('n');
+        // Digits are segmented like punctuation, so they can have their own interpretation
+        let is_romaji_punct =
+            matches!(ch, '.' | ',' | '!' | '?' | '[' | ']') || ch.is_ascii_digit();
+        let new_status = if is_escape {
+            // Escapes can be part of romaji words ("a\-b"), otherwise they are just text
+            if status == Status::RomajiWord {
+                Status::RomajiWord
+            } else {
+                Status::OtherText
             }
-            Status::RomajiPunct => {
-                if !is_romaji_punct {
-                    segs.push(InputSpan::RomajiPunct {
-                        start: last_segment_begin,
-                        end: pos,
-                    });
-                    if is_romaji_word {
-                        status = Status::RomajiWord;
-                    } else {
-                        status = Status::OtherText;
-                    }
-                    last_segment_begin = pos;
-                }
+        } else if ch == '{' {
+            Status::ExplicitOther
+        } else if ch.is_ascii_alphabetic() || matches!(ch, '-' | WORD_JOINER) || is_explicit_n {
+            Status::RomajiWord
+        } else if is_romaji_punct {
+            Status::RomajiPunct
+        } else if is_hiragana(ch) {
+            Status::KanaWord { katakana: false }
+        } else if is_katakana(ch) {
+            Status::KanaWord { katakana: true }
+        } else {
+            if ch == '}' && !warnings.contains(&SegmentWarning::UnmatchedCloseBrace) {
+                warnings.push(SegmentWarning::UnmatchedCloseBrace);
             }
+            Status::OtherText
+        };
+        if new_status == status {
+            continue;
+        }
+        let (start, end) = (last_segment_begin, pos);
+        match status {
+            Status::Init | Status::ExplicitOther => {}
+            Status::RomajiWord => segs.push(InputSpan::RomajiWord { start, end }),
+            Status::RomajiPunct => segs.push(InputSpan::RomajiPunct { start, end }),
+            Status::KanaWord { .. } => segs.push(InputSpan::KanaWord { start, end }),
+            Status::OtherText => segs.push(InputSpan::Other { start, end }),
         }
+        status = new_status;
+        last_segment_begin = if ch == '{' { pos + 1 } else { pos };
     }
     // Deal with remainder
     let start = last_segment_begin;
     let end = input_text.len();
-    'remainder_push: {
-        let remainder = match status {
-            // We don't know what to do in this case, so I guess we don't push anything (FIXME?)
-            Status::Init => break 'remainder_push,
-            Status::RomajiWord => InputSpan::RomajiWord { start, end },
-            Status::RomajiPunct => InputSpan::RomajiPunct { start, end },
-            Status::OtherText | Status::ExplicitOther => InputSpan::Other { start, end },
-        };
-        if remainder.len() != 0 {
-            segs.push(remainder);
+    let remainder = match status {
+        // Init is only the status at the very start, or right after a `}`,
+        // so there is nothing left over
+        Status::Init => None,
+        Status::RomajiWord => Some(InputSpan::RomajiWord { start, end }),
+        Status::RomajiPunct => Some(InputSpan::RomajiPunct { start, end }),
+        Status::KanaWord { .. } => Some(InputSpan::KanaWord { start, end }),
+        Status::OtherText => Some(InputSpan::Other { start, end }),
+        // The rest of the text is kept as is, like it would be with a closing `}`
+        Status::ExplicitOther => {
+            warnings.push(SegmentWarning::UnterminatedBrace);
+            Some(InputSpan::Other { start, end })
         }
+    };
+    if let Some(remainder) = remainder
+        && remainder.len() != 0
+    {
+        segs.push(remainder);
     }
     // Special behavior: Get rid of single space segments. This allows
     // nice continuous output, which Japanese readers usually expect.
     // The user can still insert two spaces if they want to insert a space.
     segs.retain(|seg| seg.index(input_text) != " ");
-    segs
+    (segs, warnings)
 }
 
 #[test]
@@ -191,5 +279,28 @@ fn test_segment() {
         "ore no [chikara]" => "ore", "no", "[", "chikara", "]";
         "saikou{english}" => "saikou", "english";
         "taisetsu mono desu{english}" => "taisetsu", "mono", "desu", "english";
+        "kan'i shin'ya" => "kan'i", "shin'ya";
+        "3ko 100en" => "3", "ko", "100", "en";
+        "kore ha すごい desu" => "kore", "ha", "すごい", "desu";
+        "ki_wo tsukete ne" => "ki_wo", "tsukete", "ne";
+        r"sa\-ba \{ok\} {a\}b} ne\." => r"sa\-ba", r" \{", r"ok\}", r"a\}b", r"ne\.";
+        "カタカナのtesuto、空ha" => "カタカナ", "の", "tesuto", "、空", "ha";
+        "すごーい{かな}" => "すごーい", "かな";
     }
 }
+
+#[test]
+fn test_segment_warnings() {
+    assert!(segment_with_warnings("abc {def}").1.is_empty());
+    assert_eq!(
+        segment_with_warnings("abc {def").1,
+        [SegmentWarning::UnterminatedBrace]
+    );
+    assert_eq!(
+        segment_with_warnings("abc} def").1,
+        [SegmentWarning::UnmatchedCloseBrace]
+    );
+    assert!(segment_with_warnings(r"abc\} def").1.is_empty());
+    assert_eq!(unescape(r"a\}b\\c\d"), r"a}b\c\d");
+    assert_eq!(unescape(&escape(r"{a\b}")), r"{a\b}");
+}
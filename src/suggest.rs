@@ -0,0 +1,298 @@
+//! Looks up the dictionary words the kana of a segment could be
+
+use {
+    crate::{
+        appstate::{CachedJmdictSuggestion, CachedSuggestions, Completion},
+        conv::KanaRomajiTable,
+        kana::RomanizationScheme,
+        worker::CancelFlag,
+    },
+    std::{cmp::Reverse, collections::BTreeMap},
+};
+
+/// Shortest typed kana that completions are looked up for
+const MIN_COMPLETION_PREFIX: usize = 2;
+/// Most completions offered
+const MAX_COMPLETIONS: usize = 20;
+
+/// Finds dictionary words starting with the kana of a segment, most likely ones first
+///
+/// Romaji that hasn't become kana yet (the "t" in "arigat") has to match too.
+fn find_completions(
+    hiragana: &str,
+    katakana: &str,
+    scheme: RomanizationScheme,
+    cancel: &CancelFlag,
+) -> Vec<Completion> {
+    let index = crate::jmdict_index::get();
+    let table = KanaRomajiTable::get(scheme);
+    let mut found: BTreeMap<u32, Completion> = BTreeMap::new();
+    for kana in [hiragana, katakana] {
+        let prefix = kana.trim_end_matches(|ch: char| ch.is_ascii_alphabetic());
+        let pending = &kana[prefix.len()..];
+        if prefix.chars().count() < MIN_COMPLETION_PREFIX {
+            continue;
+        }
+        for (reading, pos, en) in index.lookup_prefix(prefix) {
+            if cancel.is_cancelled() {
+                return Vec::new();
+            }
+            // Exact matches are regular suggestions
+            if reading == kana || !table.convert(&reading[prefix.len()..]).starts_with(pending) {
+                continue;
+            }
+            found
+                .entry(pos)
+                .or_insert(Completion { entry: en, reading });
+        }
+    }
+    let mut completions: Vec<_> = found.into_values().collect();
+    completions.sort_by_key(|completion| {
+        let (common, rank) = entry_rank(&completion.entry);
+        (!common, rank)
+    });
+    completions.truncate(MAX_COMPLETIONS);
+    completions
+}
+
+/// Finds the suggestions and completions for the kana of a segment
+pub(crate) fn find_all_suggestions(
+    hiragana: &str,
+    katakana: &str,
+    scheme: RomanizationScheme,
+    cancel: &CancelFlag,
+) -> CachedSuggestions {
+    let completions = find_completions(hiragana, katakana, scheme, cancel);
+    let mut jmdict = find_suggestions(hiragana, katakana, cancel);
+    // Typos are only looked for in finished words, a word that's still being typed
+    // (ending in romaji that's not kana yet) gets completions instead
+    let in_progress = hiragana.ends_with(|ch: char| ch.is_ascii_alphabetic());
+    if jmdict.is_empty() && completions.is_empty() && !in_progress {
+        jmdict = find_corrections(hiragana, katakana, cancel);
+    }
+    CachedSuggestions {
+        jmdict,
+        completions,
+    }
+}
+
+/// Finds dictionary entries matching the kana of a segment, most likely ones first
+fn find_suggestions(
+    hiragana: &str,
+    katakana: &str,
+    cancel: &CancelFlag,
+) -> Vec<CachedJmdictSuggestion> {
+    let index = crate::jmdict_index::get();
+    // Keyed by dictionary position, so suggestions stay in dictionary order.
+    // Earlier kinds of matches take precedence for the same entry.
+    let mut found: BTreeMap<u32, CachedJmdictSuggestion> = BTreeMap::new();
+    let root = mugo_jmdict::Root::Bare(hiragana);
+    for (pos, en) in index.lookup(hiragana) {
+        if root.matches(&en) {
+            found.entry(pos).or_insert(CachedJmdictSuggestion {
+                entry: en,
+                mugo_root: None,
+                correction: None,
+            });
+        }
+    }
+    for mugo_root in mugo::deconjugate(hiragana) {
+        if cancel.is_cancelled() {
+            break;
+        }
+        for (pos, en) in index.lookup_conj(&mugo_root) {
+            found.entry(pos).or_insert(CachedJmdictSuggestion {
+                entry: en,
+                mugo_root: Some(mugo_root.clone()),
+                correction: None,
+            });
+        }
+    }
+    let root = mugo_jmdict::Root::Bare(katakana);
+    for (pos, en) in index.lookup(katakana) {
+        if root.reading_matches(&en) {
+            found.entry(pos).or_insert(CachedJmdictSuggestion {
+                entry: en,
+                mugo_root: None,
+                correction: None,
+            });
+        }
+    }
+    let mut suggestions: Vec<_> = found.into_values().collect();
+    // Stable, so it's still dictionary order for entries that rank the same
+    suggestions.sort_by_key(|sugg| {
+        let rank = entry_rank(&sugg.entry);
+        (!rank.0, sugg.mugo_root.is_some(), rank.1)
+    });
+    suggestions
+}
+
+const MAX_CORRECTIONS: usize = 20;
+
+/// Finds entries with a reading close to the kana of a segment, for when nothing else is found
+/// for it. Closest ones come first.
+fn find_corrections(
+    hiragana: &str,
+    katakana: &str,
+    cancel: &CancelFlag,
+) -> Vec<CachedJmdictSuggestion> {
+    // The closest reading of each entry, keyed by dictionary position
+    let mut found: BTreeMap<u32, (usize, CachedJmdictSuggestion)> = BTreeMap::new();
+    for (reading, pos, en) in crate::jmdict_index::get().readings() {
+        if cancel.is_cancelled() {
+            break;
+        }
+        let Some(dist) = [hiragana, katakana]
+            .into_iter()
+            .filter_map(|typed| typo_distance(typed, reading))
+            .min()
+        else {
+            continue;
+        };
+        if found.get(&pos).is_none_or(|(best, _)| dist < *best) {
+            let sugg = CachedJmdictSuggestion {
+                entry: en,
+                mugo_root: None,
+                correction: Some(reading),
+            };
+            found.insert(pos, (dist, sugg));
+        }
+    }
+    let mut corrections: Vec<_> = found.into_values().collect();
+    corrections.sort_by_key(|(dist, sugg)| {
+        let rank = entry_rank(&sugg.entry);
+        (*dist, !rank.0, rank.1)
+    });
+    corrections
+        .into_iter()
+        .take(MAX_CORRECTIONS)
+        .map(|(_, sugg)| sugg)
+        .collect()
+}
+
+/// The edit distance between typed kana and a reading, if it's small enough to be a typo
+///
+/// Longer words are allowed more typos, and very short ones none at all.
+fn typo_distance(typed: &str, reading: &str) -> Option<usize> {
+    let max = match typed.chars().count() {
+        0..=2 => return None,
+        3..=5 => 1,
+        _ => 2,
+    };
+    if typed.chars().count().abs_diff(reading.chars().count()) > max {
+        return None;
+    }
+    let dist = strsim::levenshtein(typed, reading);
+    (dist <= max).then_some(dist)
+}
+
+/// Whether the entry is common, and how frequent it is (lower is more frequent),
+/// going by the best JMdict priority markers of its elements
+fn entry_rank(en: &jmdict::Entry) -> (bool, (u16, Reverse<u8>)) {
+    let priorities = en
+        .kanji_elements()
+        .map(|elem| elem.priority)
+        .chain(en.reading_elements().map(|elem| elem.priority));
+    let mut common = false;
+    let mut best = (u16::MAX, Reverse(0));
+    for prio in priorities {
+        common |= prio.is_common();
+        best = best.min(priority_rank(&prio));
+    }
+    (common, best)
+}
+
+/// The nfXX frequency bucket first (absent is last), then the news/ichi/spec/gai markers,
+/// where rank 1 counts double
+fn priority_rank(prio: &jmdict::Priority) -> (u16, Reverse<u8>) {
+    let bucket = match prio.frequency_bucket {
+        0 => u16::MAX,
+        n => n,
+    };
+    let markers = [prio.news, prio.ichimango, prio.additional, prio.loanwords]
+        .iter()
+        .map(|corpus| match corpus {
+            jmdict::PriorityInCorpus::Primary => 2,
+            jmdict::PriorityInCorpus::Secondary => 1,
+            jmdict::PriorityInCorpus::Absent => 0,
+        })
+        .sum();
+    (bucket, Reverse(markers))
+}
+
+#[test]
+fn test_corrections_only_for_finished_words() {
+    let find = |romaji| {
+        let scheme = RomanizationScheme::Hepburn;
+        let hiragana = crate::conv::romaji_to_kana(romaji, scheme.hiragana());
+        let katakana = crate::conv::romaji_to_kana(romaji, scheme.katakana());
+        find_all_suggestions(&hiragana, &katakana, scheme, &CancelFlag::default())
+    };
+    let typo = find("arigtou");
+    assert!(
+        typo.jmdict
+            .iter()
+            .any(|sugg| sugg.correction == Some("ありがとう"))
+    );
+    let typing = find("arigat");
+    assert!(!typing.completions.is_empty());
+    assert!(typing.jmdict.iter().all(|sugg| sugg.correction.is_none()));
+}
+
+#[test]
+fn test_typo_distance() {
+    // Romaji that doesn't convert is left in the kana, like the "g" of "arigtou"
+    assert_eq!(typo_distance("ありgとう", "ありがとう"), Some(1));
+    assert_eq!(typo_distance("べんきょ", "べんきょう"), Some(1));
+    assert_eq!(typo_distance("べんきょう", "べんきょう"), Some(0));
+    assert_eq!(typo_distance("かく", "かさ"), None);
+    assert_eq!(typo_distance("がくせい", "せんせい"), None);
+}
+
+#[test]
+fn test_suru_noun_suggestion() {
+    let hiragana =
+        crate::conv::romaji_to_kana("jittoshiterarenai", RomanizationScheme::Hepburn.hiragana());
+    let katakana = crate::kana::hiragana_to_katakana(&hiragana);
+    let suggestions = find_suggestions(&hiragana, &katakana, &CancelFlag::default());
+    assert!(suggestions.iter().any(|sugg| {
+        sugg.mugo_root.is_some()
+            && sugg
+                .entry
+                .kanji_elements()
+                .any(|elem| elem.text == "凝乎と")
+    }));
+}
+
+#[test]
+fn test_priority_rank() {
+    use jmdict::{Priority, PriorityInCorpus::*};
+    let news1_nf02 = Priority {
+        news: Primary,
+        frequency_bucket: 2,
+        ..Priority::default()
+    };
+    let news1_nf10 = Priority {
+        frequency_bucket: 10,
+        ..news1_nf02
+    };
+    let ichi1_spec1 = Priority {
+        ichimango: Primary,
+        additional: Primary,
+        ..Priority::default()
+    };
+    let gai2 = Priority {
+        loanwords: Secondary,
+        ..Priority::default()
+    };
+    let ranks = [
+        news1_nf02,
+        news1_nf10,
+        ichi1_spec1,
+        gai2,
+        Priority::default(),
+    ]
+    .map(|prio| priority_rank(&prio));
+    assert!(ranks.is_sorted());
+    assert!(!ranks.windows(2).any(|pair| pair[0] == pair[1]));
+}
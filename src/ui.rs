@@ -3,6 +3,7 @@ mod dict;
 mod help;
 pub mod input;
 mod kanji_ui;
+mod learned;
 mod theme;
 
 pub use self::{
@@ -11,11 +12,16 @@ pub use self::{
     help::help_ui,
     input::input_ui,
     kanji_ui::{KanjiUiState, kanji_ui},
+    learned::learned_ui,
 };
 use {
     crate::{
         appstate::{AppState, UiState},
         egui::{self, TextFormat, text::LayoutJob},
+        furigana::{self, FuriganaPart},
+        kana::RomanizationScheme,
+        kanji::KanjiDb,
+        output::OutputFormat,
     },
     mugo_jmdict::RootKindExt as _,
     theme::theme_ui,
@@ -30,10 +36,11 @@ fn dict_en_ui_scroll(
     en: &jmdict::Entry,
     root: Option<&mugo::Root>,
     kanji_idx: Option<&mut usize>,
+    kanji_db: &KanjiDb,
 ) {
     egui::ScrollArea::vertical()
         .id_salt("en_scroll_vert")
-        .show(ui, |ui| dict_en_ui(ui, en, root, kanji_idx));
+        .show(ui, |ui| dict_en_ui(ui, en, root, kanji_idx, kanji_db));
 }
 
 fn dict_en_ui(
@@ -41,6 +48,7 @@ fn dict_en_ui(
     en: &jmdict::Entry,
     root: Option<&mugo::Root>,
     mut kanji_idx: Option<&mut usize>,
+    kanji_db: &KanjiDb,
 ) {
     {
         let mut steps_str = String::new();
@@ -69,25 +77,55 @@ fn dict_en_ui(
             for (i, elem) in en.kanji_elements().enumerate() {
                 ui.spacing_mut().item_spacing = egui::vec2(8.0, 0.0);
                 let mut layout_job = LayoutJob::default();
-                for char in elem.text.chars() {
-                    let char_str = char.to_string();
-                    if char_is_hiragana(char) {
-                        layout_job.append(
-                            &char_str,
-                            0.0,
-                            TextFormat {
-                                font_id: egui::FontId::new(14.0, egui::FontFamily::Proportional),
-                                color: egui::Color32::DARK_GRAY,
-                                ..Default::default()
-                            },
-                        );
-                    } else {
+                let parts = en
+                    .reading_elements()
+                    .find_map(|reading| furigana::align(elem.text, reading.text, kanji_db))
+                    .unwrap_or_else(|| {
+                        vec![FuriganaPart {
+                            text: elem.text,
+                            reading: None,
+                        }]
+                    });
+                for part in parts {
+                    for char in part.text.chars() {
+                        let char_str = char.to_string();
+                        if char_is_hiragana(char) {
+                            layout_job.append(
+                                &char_str,
+                                0.0,
+                                TextFormat {
+                                    font_id: egui::FontId::new(
+                                        14.0,
+                                        egui::FontFamily::Proportional,
+                                    ),
+                                    color: egui::Color32::DARK_GRAY,
+                                    ..Default::default()
+                                },
+                            );
+                        } else {
+                            layout_job.append(
+                                &char_str,
+                                0.0,
+                                TextFormat {
+                                    font_id: egui::FontId::new(
+                                        18.0,
+                                        egui::FontFamily::Proportional,
+                                    ),
+                                    color: egui::Color32::WHITE,
+                                    ..Default::default()
+                                },
+                            );
+                        }
+                    }
+                    // Reading of the kanji, raised like furigana
+                    if let Some(reading) = part.reading {
                         layout_job.append(
-                            &char_str,
+                            reading,
                             0.0,
                             TextFormat {
-                                font_id: egui::FontId::new(18.0, egui::FontFamily::Proportional),
-                                color: egui::Color32::WHITE,
+                                font_id: egui::FontId::new(10.0, egui::FontFamily::Proportional),
+                                color: egui::Color32::LIGHT_BLUE,
+                                valign: egui::Align::TOP,
                                 ..Default::default()
                             },
                         );
@@ -179,6 +217,7 @@ pub fn update(ui: &mut egui::Ui, app: &mut AppState) -> bool {
         UiState::About => about_ui(ui, app),
         UiState::Help => help_ui(ui, app),
         UiState::Theme => theme_ui(ui, app),
+        UiState::Learned => learned_ui(ui, app),
     });
     #[cfg(feature = "ipc")]
     handle_ipc_messages(app, ui);
@@ -217,12 +256,36 @@ fn show_menu_button(app: &mut AppState, ui: &mut egui::Ui) {
     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
         ui.menu_button("☰ Menu", |ui| {
             if ui.button("Normalize case").clicked() {
-                app.romaji_buf.make_ascii_lowercase();
+                app.normalize_case();
+            }
+            if ui
+                .button("Convert Japanese to romaji")
+                .on_hover_text("Make pasted Japanese text editable")
+                .clicked()
+            {
+                app.convert_japanese_to_romaji();
             }
+            ui.menu_button("Romanization", |ui| {
+                for scheme in RomanizationScheme::ALL {
+                    ui.radio_value(&mut app.romanization, scheme, scheme.label());
+                }
+            });
+            ui.checkbox(&mut app.auto_split, "Split words automatically")
+                .on_hover_text("Split unspaced romaji into dictionary words");
+            ui.checkbox(&mut app.auto_apply_learned, "Apply learned words")
+                .on_hover_text("Pick the usual kanji for a word once you're done typing it");
+            ui.menu_button("Copy format", |ui| {
+                for format in OutputFormat::ALL {
+                    ui.radio_value(&mut app.output_format, format, format.label());
+                }
+            });
             ui.separator();
             if ui.button("Theme configuration...").clicked() {
                 app.ui_state = UiState::Theme;
             }
+            if ui.button("Learned words...").clicked() {
+                app.ui_state = UiState::Learned;
+            }
             ui.separator();
             if ui.button("？ Help").clicked() {
                 app.ui_state = UiState::Help;
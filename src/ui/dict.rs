@@ -4,7 +4,9 @@ use {
         appstate::{AppState, UiState},
         conv::romaji_to_kana,
         egui,
-        kana::HIRAGANA,
+        jmdict_index::EntryId,
+        kana::RomanizationScheme,
+        worker::{CancelFlag, Job},
     },
 };
 
@@ -19,11 +21,19 @@ pub fn dict_ui(ui: &mut egui::Ui, app: &mut AppState) {
             inp.key_pressed(egui::Key::F4),
         )
     });
-    if up_arrow {
-        app.dict_ui_state.selected = app.dict_ui_state.selected.saturating_sub(1);
-    }
-    if down_arrow {
-        app.dict_ui_state.selected += 1;
+    if up_arrow || down_arrow {
+        let state = &mut app.dict_ui_state;
+        let pos = state
+            .selected
+            .and_then(|id| state.entry_buf.iter().position(|en| EntryId::of(en) == id));
+        let new_pos = match pos {
+            Some(pos) if up_arrow => pos.saturating_sub(1),
+            Some(pos) => pos + 1,
+            None => 0,
+        };
+        if let Some(en) = state.entry_buf.get(new_pos) {
+            state.selected = Some(EntryId::of(en));
+        }
     }
     let mut want_focus = false;
     ui.horizontal(|ui| {
@@ -68,11 +78,11 @@ pub fn dict_ui(ui: &mut egui::Ui, app: &mut AppState) {
     });
     ui.columns(2, |cols| {
         dict_list_ui(&mut cols[0], app);
-        let Some(en) = app.dict_ui_state.entry_buf.get(app.dict_ui_state.selected) else {
+        let Some(en) = app.dict_ui_state.selected.and_then(EntryId::entry) else {
             cols[1].label("<Couldn't get entry>");
             return;
         };
-        dict_en_ui_scroll(&mut cols[1], en, None, None);
+        dict_en_ui_scroll(&mut cols[1], &en, None, None, &app.kanji_db);
     });
     app.dict_ui_state.focus_textinput = want_focus;
 }
@@ -132,12 +142,62 @@ fn test_kanji_query_from_str() {
     );
 }
 
+/// What the entry list is searched for: the lookup method and the query
+fn search_key(state: &DictUiState, scheme: RomanizationScheme) -> (LookupMethod, String) {
+    let query = match state.lookup_method {
+        LookupMethod::Kana => romaji_to_kana(&state.kana_search_buf, scheme.hiragana()),
+        LookupMethod::English => state.english_search_buf.clone(),
+        LookupMethod::Kanji => state.kanji_search_buf.clone(),
+    };
+    (state.lookup_method, query)
+}
+
+/// Finds the entries for the entry list, stopping early if cancelled
+fn search_entries(method: LookupMethod, query: &str, cancel: &CancelFlag) -> Vec<jmdict::Entry> {
+    let entries = jmdict::entries().take_while(|_| !cancel.is_cancelled());
+    match method {
+        LookupMethod::Kana => entries
+            .filter(|en| en.reading_elements().any(|elem| elem.text.contains(query)))
+            .collect(),
+        LookupMethod::English => {
+            let mut entries: Vec<_> = entries
+                .filter(|en| {
+                    en.senses()
+                        .any(|sense| sense.glosses().any(|gloss| gloss.text.contains(query)))
+                })
+                .collect();
+            entries.sort_by_key(|en| {
+                strsim::levenshtein(
+                    query,
+                    en.senses().next().unwrap().glosses().next().unwrap().text,
+                )
+            });
+            entries
+        }
+        LookupMethod::Kanji => {
+            let query = KanjiQuery::from_str(query);
+            entries
+                .filter(|en| {
+                    en.kanji_elements().any(|elem| {
+                        elem.text.contains(query.contains)
+                            && query.starts_with.is_none_or(|ch| elem.text.starts_with(ch))
+                            && query
+                                .n_chars
+                                .is_none_or(|n| elem.text.chars().count() == usize::from(n))
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
 fn dict_list_ui(ui: &mut egui::Ui, app: &mut AppState) {
     let search_buf = match app.dict_ui_state.lookup_method {
         LookupMethod::Kana => &mut app.dict_ui_state.kana_search_buf,
         LookupMethod::English => &mut app.dict_ui_state.english_search_buf,
         LookupMethod::Kanji => &mut app.dict_ui_state.kanji_search_buf,
     };
+    let searching = app.dict_ui_state.search_job.is_running();
     let mut kana_converted = false;
     let re = ui
         .horizontal(|ui| {
@@ -147,56 +207,37 @@ fn dict_list_ui(ui: &mut egui::Ui, app: &mut AppState) {
                 .clicked()
                 || ui.input(|inp| inp.key_pressed(egui::Key::Space) && inp.modifiers.ctrl)
             {
-                let out = crate::conv::romaji_to_kana(search_buf, &HIRAGANA);
+                let out = crate::conv::romaji_to_kana(search_buf, app.romanization.hiragana());
                 if !out.is_empty() {
                     *search_buf = out;
                 }
                 kana_converted = true;
             }
-            ui.add(egui::TextEdit::singleline(search_buf).hint_text("Filter"))
+            let re = ui.add(egui::TextEdit::singleline(search_buf).hint_text("Filter"));
+            if searching {
+                ui.spinner();
+            }
+            re
         })
         .inner;
+    let key = search_key(&app.dict_ui_state, app.romanization);
     if re.changed() || kana_converted || app.dict_ui_state.focus_textinput {
-        app.dict_ui_state.selected = 0;
-        match app.dict_ui_state.lookup_method {
-            LookupMethod::Kana => {
-                let kana = romaji_to_kana(&app.dict_ui_state.kana_search_buf, &HIRAGANA);
-                app.dict_ui_state.entry_buf = jmdict::entries()
-                    .filter(|en| en.reading_elements().any(|elem| elem.text.contains(&kana)))
-                    .collect();
-            }
-            LookupMethod::English => {
-                app.dict_ui_state.entry_buf = jmdict::entries()
-                    .filter(|en| {
-                        en.senses().any(|sense| {
-                            sense.glosses().any(|gloss| {
-                                gloss.text.contains(&app.dict_ui_state.english_search_buf)
-                            })
-                        })
-                    })
-                    .collect();
-                app.dict_ui_state.entry_buf.sort_by_key(|en| {
-                    strsim::levenshtein(
-                        &app.dict_ui_state.english_search_buf,
-                        en.senses().next().unwrap().glosses().next().unwrap().text,
-                    )
-                });
-            }
-            LookupMethod::Kanji => {
-                let query = KanjiQuery::from_str(&app.dict_ui_state.kanji_search_buf);
-                app.dict_ui_state.entry_buf = jmdict::entries()
-                    .filter(|en| {
-                        en.kanji_elements().any(|elem| {
-                            elem.text.contains(query.contains)
-                                && query.starts_with.is_none_or(|ch| elem.text.starts_with(ch))
-                                && query
-                                    .n_chars
-                                    .is_none_or(|n| elem.text.chars().count() == usize::from(n))
-                        })
-                    })
-                    .collect();
-            }
+        let (method, query) = key.clone();
+        app.dict_ui_state
+            .search_job
+            .start(key, ui.ctx(), move |cancel| {
+                search_entries(method, &query, cancel)
+            });
+    } else if let Some(entries) = app.dict_ui_state.search_job.poll(&key) {
+        let state = &mut app.dict_ui_state;
+        // Keep the selection if it's still in there
+        if !state
+            .selected
+            .is_some_and(|id| entries.iter().any(|en| EntryId::of(en) == id))
+        {
+            state.selected = entries.first().map(EntryId::of);
         }
+        state.entry_buf = entries;
     }
     if app.dict_ui_state.focus_textinput {
         re.request_focus();
@@ -207,13 +248,12 @@ fn dict_list_ui(ui: &mut egui::Ui, app: &mut AppState) {
         app.dict_ui_state.entry_buf.len(),
         |ui, range| {
             ui.set_min_width(200.0);
-            let start = range.start;
-            for (i, en) in app.dict_ui_state.entry_buf[range].iter().enumerate() {
-                let idx = start + i;
+            for en in &app.dict_ui_state.entry_buf[range] {
+                let id = EntryId::of(en);
                 let s;
                 if ui
                     .selectable_label(
-                        app.dict_ui_state.selected == idx,
+                        app.dict_ui_state.selected == Some(id),
                         match app.dict_ui_state.lookup_method {
                             LookupMethod::Kana => match en.kanji_elements().next() {
                                 Some(kanji) => {
@@ -252,7 +292,7 @@ fn dict_list_ui(ui: &mut egui::Ui, app: &mut AppState) {
                     )
                     .clicked()
                 {
-                    app.dict_ui_state.selected = idx;
+                    app.dict_ui_state.selected = Some(id);
                 }
             }
         },
@@ -264,11 +304,15 @@ pub struct DictUiState {
     kanji_search_buf: String,
     english_search_buf: String,
     entry_buf: Vec<jmdict::Entry>,
-    selected: usize,
+    /// Fills `entry_buf` in the background
+    search_job: Job<(LookupMethod, String), Vec<jmdict::Entry>>,
+    /// Stays selected as long as it's in the search results
+    selected: Option<EntryId>,
     pub focus_textinput: bool,
     lookup_method: LookupMethod,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum LookupMethod {
     Kana,
     English,
@@ -277,12 +321,14 @@ enum LookupMethod {
 
 impl Default for DictUiState {
     fn default() -> Self {
+        let entry_buf: Vec<_> = jmdict::entries().collect();
         Self {
             kana_search_buf: String::new(),
             kanji_search_buf: String::new(),
             english_search_buf: String::new(),
-            entry_buf: jmdict::entries().collect(),
-            selected: 0,
+            selected: entry_buf.first().map(EntryId::of),
+            entry_buf,
+            search_job: Job::default(),
             focus_textinput: false,
             lookup_method: LookupMethod::Kana,
         }
@@ -2,13 +2,13 @@ use {
     super::{dict_en_ui, dict_en_ui_scroll},
     crate::{
         appstate::{AppState, CachedSuggestions, HistoryEntry, UiState},
-        conv::{self, Intp, IntpMap, romaji_to_kana, with_input_span_converted_form},
+        conv::{self, Intp, IntpMap, with_input_span_converted_form},
         egui::{
             self, Color32, Modifiers,
             text::{CCursor, CCursorRange},
         },
-        kana::{HIRAGANA, KATAKANA},
         kanji::KanjiDb,
+        output,
         segment::InputSpan,
     },
     egui_extras::{Size, StripBuilder},
@@ -32,25 +32,57 @@ pub enum InputUiAction {
 
 pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
     ensure_ui_sanity(app);
+    app.poll_suggestions();
     let mut repopulate_suggestion_cache = false;
     let mut copy_jap_clicked = false;
-    let (ctrl_enter, f1, f2, f3, f4, esc, tab, shift, alt_left, alt_right, c_obr, c_cbr) = ui
-        .input_mut(|inp| {
-            (
-                inp.consume_key(Modifiers::CTRL, egui::Key::Enter),
-                inp.key_pressed(egui::Key::F1),
-                inp.key_pressed(egui::Key::F2),
-                inp.key_pressed(egui::Key::F3),
-                inp.key_pressed(egui::Key::F4),
-                inp.key_pressed(egui::Key::Escape),
-                inp.consume_key(Modifiers::NONE, egui::Key::Tab),
-                inp.modifiers.shift,
-                inp.consume_key(Modifiers::ALT, egui::Key::ArrowLeft),
-                inp.consume_key(Modifiers::ALT, egui::Key::ArrowRight),
-                inp.consume_key(Modifiers::CTRL, egui::Key::OpenBracket),
-                inp.consume_key(Modifiers::CTRL, egui::Key::CloseBracket),
-            )
-        });
+    let (
+        ctrl_enter,
+        f1,
+        f2,
+        f3,
+        f4,
+        esc,
+        tab,
+        shift,
+        alt_left,
+        alt_right,
+        c_obr,
+        c_cbr,
+        c_semicolon,
+        c_quote,
+        c_space,
+        redo,
+        undo,
+    ) = ui.input_mut(|inp| {
+        (
+            inp.consume_key(Modifiers::CTRL, egui::Key::Enter),
+            inp.key_pressed(egui::Key::F1),
+            inp.key_pressed(egui::Key::F2),
+            inp.key_pressed(egui::Key::F3),
+            inp.key_pressed(egui::Key::F4),
+            inp.key_pressed(egui::Key::Escape),
+            inp.consume_key(Modifiers::NONE, egui::Key::Tab),
+            inp.modifiers.shift,
+            inp.consume_key(Modifiers::ALT, egui::Key::ArrowLeft),
+            inp.consume_key(Modifiers::ALT, egui::Key::ArrowRight),
+            inp.consume_key(Modifiers::CTRL, egui::Key::OpenBracket),
+            inp.consume_key(Modifiers::CTRL, egui::Key::CloseBracket),
+            inp.consume_key(Modifiers::CTRL, egui::Key::Semicolon),
+            inp.consume_key(Modifiers::CTRL, egui::Key::Quote),
+            inp.consume_key(Modifiers::CTRL, egui::Key::Space),
+            // Before the TextEdit gets them, and redo first, as ctrl+z also matches ctrl+shift+z
+            inp.consume_key(Modifiers::CTRL | Modifiers::SHIFT, egui::Key::Z),
+            inp.consume_key(Modifiers::CTRL, egui::Key::Z),
+        )
+    });
+    if undo {
+        app.undo();
+        repopulate_suggestion_cache = true;
+    }
+    if redo {
+        app.redo();
+        repopulate_suggestion_cache = true;
+    }
     if esc {
         app.hide_requested = true;
     }
@@ -65,7 +97,7 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                 app.selected_segment = app.segments.len().saturating_sub(1);
                 break;
             }
-            if app.segments[app.selected_segment].is_romaji_word() {
+            if app.segments[app.selected_segment].is_word() {
                 break;
             }
         }
@@ -95,18 +127,27 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                     app.intp.insert(
                         app.selected_segment,
                         Intp::Dictionary {
-                            cached_sug_idx: selected_sug,
-                            en: sug.entry,
+                            entry: sug.id(),
                             kanji_idx: 0,
                             root: sug.mugo_root.clone(),
                         },
                     );
-                } else {
+                    app.pick_word(app.selected_segment);
+                } else if app.selected_suggestion.is_none() {
                     app.intp.remove(&app.selected_segment);
                 }
+                // A selected completion only takes effect once it's accepted
             }
         }
     }
+    if c_space {
+        // The selected completion, or the first one
+        let idx = app
+            .selected_suggestion
+            .and_then(|i| i.checked_sub(app.cached_suggestions.jmdict.len()))
+            .unwrap_or(0);
+        repopulate_suggestion_cache |= accept_completion(app, idx);
+    }
     ui.horizontal(|ui| {
         if ui.button("[F1] 📖 Dict").clicked() || f1 {
             app.ui_state = UiState::Dict;
@@ -131,12 +172,15 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
         ui.add_enabled_ui(enabled, |ui| {
             ui.menu_button("🕓 History", |ui| {
                 ui.style_mut().wrap_mode = Some(egui::TextWrapMode::Extend);
+                let mut restore = None;
                 for entry in &app.history {
                     if ui.button(&entry.romaji_buf).clicked() {
-                        app.romaji_buf = entry.romaji_buf.clone();
-                        app.intp = entry.intp.clone();
+                        restore = Some((entry.romaji_buf.clone(), entry.intp.clone()));
                     }
                 }
+                if let Some((romaji, intp)) = restore {
+                    app.set_romaji(romaji, intp);
+                }
                 ui.separator();
                 if ui.button("🗑 Clear").clicked() {
                     app.history.clear();
@@ -146,7 +190,7 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
         crate::ui::show_menu_button(app, ui);
     });
     ui.separator();
-    // Character (not byte) position of the text cursor in the romaji editor
+    // Byte position of the text cursor in the romaji editor
     let mut text_cursor = 0;
     let mut set_textedit_scroll_offset = None;
     let mut scroll_out = egui::ScrollArea::vertical()
@@ -161,12 +205,17 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                 repopulate_suggestion_cache = true;
             }
             if let Some(range) = &mut out.cursor_range {
-                text_cursor = range.primary.index;
+                text_cursor = app
+                    .romaji_buf
+                    .char_indices()
+                    .nth(range.primary.index)
+                    .map_or(app.romaji_buf.len(), |(i, _)| i);
             }
             if let Some(InputUiAction::SetCursor(pos)) = app.input_ui_action.as_ref() {
+                let char_pos = app.romaji_buf[..*pos].chars().count();
                 out.state
                     .cursor
-                    .set_char_range(Some(CCursorRange::one(CCursor::new(*pos))));
+                    .set_char_range(Some(CCursorRange::one(CCursor::new(char_pos))));
                 out.state.store(ui.ctx(), out.response.id);
                 set_textedit_scroll_offset = Some(app.out_scroll_last_offset);
                 app.input_ui_action = None;
@@ -180,9 +229,11 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
     ui.separator();
     // region: input state change handling
     let mut segmentation_count_changed = false;
-    let new = crate::segment::segment(&app.romaji_buf);
-    crate::detect_edit::detect_edit_update_index_map(&mut app.intp, &app.segments, &new);
-    app.segments = new;
+    app.update_segments();
+    app.split_unsplit_words(ui.ctx());
+    for warning in &app.segment_warnings {
+        ui.colored_label(Color32::YELLOW, format!("⚠ {}", warning.message()));
+    }
     let new_len = app.segments.len();
     if new_len > app.last_segs_len {
         segmentation_count_changed = true;
@@ -193,7 +244,15 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
     }
     app.last_selected_segment = app.selected_segment;
     // endregion: input state change handling
-    let japanese = conv::to_japanese(&app.romaji_buf, &app.segments, &app.intp, &app.kanji_db);
+    let mut clicked_completion = None;
+    let japanese = output::to_japanese(
+        &app.romaji_buf,
+        &app.segments,
+        &app.intp,
+        app.romanization,
+        &app.kanji_db,
+        app.output_format,
+    );
     StripBuilder::new(ui)
         .size(Size::exact(120.0))
         .size(Size::remainder())
@@ -211,17 +270,26 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                             if c_cbr {
                                 app.intp.insert(app.selected_segment, Intp::Katakana);
                             }
+                            if c_semicolon {
+                                app.intp
+                                    .insert(app.selected_segment, Intp::HalfwidthKatakana);
+                            }
+                            if c_quote {
+                                app.intp.insert(app.selected_segment, Intp::Fullwidth);
+                            }
                         }
                         ui.horizontal_wrapped(|ui| {
                             let spacing = ui.spacing_mut();
                             spacing.item_spacing = egui::vec2(0.0, 0.0);
                             for (i, span) in app.segments.iter().enumerate() {
                                 let mut remove_intp = None;
+                                let mut set_intp = None;
                                 with_input_span_converted_form(
                                     span,
                                     i,
                                     &app.romaji_buf,
                                     &app.intp,
+                                    app.romanization,
                                     &app.kanji_db,
                                     |conv_text| {
                                         let mut text = egui::RichText::new(conv_text);
@@ -234,6 +302,10 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                                         let mut re = ui.add(
                                             egui::Label::new(text).sense(egui::Sense::click()),
                                         );
+                                        // Start editing custom text from what's shown
+                                        if re.secondary_clicked() {
+                                            app.custom_intp_buf = conv_text.to_owned();
+                                        }
                                         re.context_menu(|ui| {
                                             if ui.button("Edit here").clicked() {
                                                 app.input_ui_action = Some(
@@ -250,10 +322,48 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                                             {
                                                 remove_intp = Some(i);
                                             }
+                                            if !matches!(span, InputSpan::Other { .. }) {
+                                                ui.separator();
+                                                ui.horizontal(|ui| {
+                                                    let re = ui.text_edit_singleline(
+                                                        &mut app.custom_intp_buf,
+                                                    );
+                                                    let enter = re.lost_focus()
+                                                        && ui.input(|inp| {
+                                                            inp.key_pressed(egui::Key::Enter)
+                                                        });
+                                                    if ui
+                                                        .button("Set custom text")
+                                                        .on_hover_text(
+                                                            "Use any text for this segment \
+                                                             (empty text removes it)",
+                                                        )
+                                                        .clicked()
+                                                        || enter
+                                                    {
+                                                        // Empty custom text couldn't be clicked
+                                                        // to edit again, so it removes it
+                                                        if app.custom_intp_buf.is_empty() {
+                                                            if matches!(
+                                                                app.intp.get(&i),
+                                                                Some(Intp::Custom(_))
+                                                            ) {
+                                                                remove_intp = Some(i);
+                                                            }
+                                                        } else {
+                                                            set_intp = Some(Intp::Custom(
+                                                                app.custom_intp_buf.clone(),
+                                                            ));
+                                                        }
+                                                        ui.close();
+                                                    }
+                                                });
+                                            }
                                         });
                                         let (InputSpan::Other { start, end }
                                         | InputSpan::RomajiPunct { start, end }
-                                        | InputSpan::RomajiWord { start, end }) = *span;
+                                        | InputSpan::RomajiWord { start, end }
+                                        | InputSpan::KanaWord { start, end }) = *span;
                                         re = re.on_hover_text(&app.romaji_buf[start..end]);
                                         if re.clicked() {
                                             app.selected_segment = i;
@@ -263,10 +373,14 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                                 if let Some(idx) = remove_intp {
                                     app.intp.remove(&idx);
                                 }
+                                if let Some(intp) = set_intp {
+                                    app.intp.insert(i, intp);
+                                }
                             }
                         });
                         if copy_jap_clicked {
                             app.set_clipboard_text(ui.ctx(), &japanese);
+                            app.learn_choices();
                             app.history.push(HistoryEntry {
                                 romaji_buf: app.romaji_buf.clone(),
                                 intp: app.intp.clone(),
@@ -276,29 +390,54 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
                 app.out_scroll_last_offset = scroll_out.state.offset.y;
             });
             strip.strip(|builder| {
-                let Some(&InputSpan::RomajiWord { start, end }) =
-                    app.segments.get(app.selected_segment)
+                let selected_completion = app
+                    .selected_suggestion
+                    .and_then(|i| i.checked_sub(app.cached_suggestions.jmdict.len()));
+                let Some((hiragana, katakana)) = app
+                    .segments
+                    .get(app.selected_segment)
+                    .and_then(|&span| conv::word_kana(span, &app.romaji_buf, app.romanization))
                 else {
                     return;
                 };
-                let romaji = &app.romaji_buf[start..end];
-                suggestion_ui_strip(
-                    romaji,
+                let dict_choice = |intp: &IntpMap| match intp.get(&app.selected_segment) {
+                    Some(Intp::Dictionary {
+                        entry, kanji_idx, ..
+                    }) => Some((*entry, *kanji_idx)),
+                    _ => None,
+                };
+                let old_choice = dict_choice(&app.intp);
+                clicked_completion = suggestion_ui_strip(
+                    hiragana.trim(),
+                    katakana.trim(),
                     app.selected_segment,
                     &mut app.intp,
                     &app.cached_suggestions,
+                    app.suggestion_job.is_running(),
+                    selected_completion,
                     &app.kanji_db,
                     builder,
                     sel_changed,
                 );
+                // Clicking a word or one of its kanji picks it
+                if let new_choice @ Some(_) = dict_choice(&app.intp)
+                    && new_choice != old_choice
+                {
+                    app.pick_word(app.selected_segment);
+                }
             });
         });
+    if let Some(idx) = clicked_completion {
+        repopulate_suggestion_cache |= accept_completion(app, idx);
+    }
     if ctrl_enter {
         app.set_clipboard_text(ui.ctx(), &japanese);
+        app.learn_choices();
         app.history.push(HistoryEntry {
             romaji_buf: app.romaji_buf.clone(),
             intp: app.intp.clone(),
         });
+        app.undo_stack.break_merge(&app.romaji_buf, &app.intp);
         app.romaji_buf.clear();
         app.intp.clear();
         app.hide_requested = true;
@@ -312,7 +451,7 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
             if span.contains_cursor(text_cursor) {
                 found_cursor_span = true;
             }
-            if found_cursor_span && span.is_romaji_word() {
+            if found_cursor_span && span.is_word() {
                 app.selected_segment = i;
                 any_set = true;
                 break;
@@ -321,11 +460,38 @@ pub fn input_ui(ui: &mut egui::Ui, app: &mut AppState) {
         if !any_set {
             app.selected_segment = 0;
         }
+        // The word before the one being typed is done
+        if app.auto_apply_learned
+            && let Some(done) = (0..app.selected_segment)
+                .rev()
+                .find(|&i| app.segments[i].is_word())
+        {
+            app.apply_learned(done);
+        }
     }
+    let time = ui.input(|inp| inp.time);
+    app.undo_stack.update(&app.romaji_buf, &app.intp, time);
     if repopulate_suggestion_cache {
         // Also clear the selected suggestion
         app.selected_suggestion = None;
-        app.repopulate_suggestion_cache();
+        app.repopulate_suggestion_cache(ui.ctx());
+    }
+}
+
+/// Replaces the selected segment with a completion, returns whether it was done
+fn accept_completion(app: &mut AppState, idx: usize) -> bool {
+    let Some(cursor) = app.accept_completion(idx) else {
+        return false;
+    };
+    app.input_ui_action = Some(InputUiAction::SetCursor(cursor));
+    true
+}
+
+/// Tab cycles through the suggestions with kanji, and then through the completions
+fn is_tab_stop(cached_suggestions: &CachedSuggestions, i: usize) -> bool {
+    match cached_suggestions.jmdict.get(i) {
+        Some(sugg) => sugg.entry.kanji_elements().len() != 0,
+        None => i - cached_suggestions.jmdict.len() < cached_suggestions.completions.len(),
     }
 }
 
@@ -334,20 +500,11 @@ fn kanji_sugg_cursor_forward(
     sug: &mut Option<usize>,
     sel_changed: &mut bool,
 ) {
-    let new = sug.is_none();
-    let starting_idx = sug.unwrap_or(0);
-    for (i, sugg) in
-        cached_suggestions
-            .jmdict
-            .iter()
-            .enumerate()
-            .skip(if new { 0 } else { starting_idx + 1 })
-    {
-        if sugg.entry.kanji_elements().len() != 0 {
-            *sel_changed = true;
-            *sug = Some(i);
-            break;
-        }
+    let start = sug.map_or(0, |sug| sug + 1);
+    let end = cached_suggestions.jmdict.len() + cached_suggestions.completions.len();
+    if let Some(i) = (start..end).find(|&i| is_tab_stop(cached_suggestions, i)) {
+        *sel_changed = true;
+        *sug = Some(i);
     }
 }
 
@@ -361,8 +518,7 @@ fn kanji_sugg_cursor_back(
         let mut i = *sug;
         while i > 0 {
             i -= 1;
-            let sugg = &cached_suggestions.jmdict[i];
-            if sugg.entry.kanji_elements().len() != 0 {
+            if is_tab_stop(cached_suggestions, i) {
                 *sug = i;
                 return;
             }
@@ -381,22 +537,27 @@ fn segment_sel_nav_left(app: &mut AppState) {
             || app
                 .segments
                 .get(app.selected_segment)
-                .is_some_and(|seg| seg.is_romaji_word())
+                .is_some_and(|seg| seg.is_word())
         {
             break;
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn suggestion_ui_strip(
-    seg: &str,
+    hiragana: &str,
+    katakana: &str,
     intp_idx: usize,
     intp: &mut IntpMap,
     cached_suggestions: &CachedSuggestions,
+    loading: bool,
+    selected_completion: Option<usize>,
     kanji_db: &KanjiDb,
     strip_builder: StripBuilder,
     sel_changed: bool,
-) {
+) -> Option<usize> {
+    let mut clicked_completion = None;
     strip_builder
         .clip(true)
         .size(Size::exact(100.0))
@@ -423,13 +584,44 @@ fn suggestion_ui_strip(
                             "Katakana (ctrl+])",
                             Intp::Katakana,
                         );
+                        ui.separator();
+                        intp_button(
+                            intp,
+                            intp_idx,
+                            ui,
+                            "ﾊ",
+                            "Half-width katakana (ctrl+;)",
+                            Intp::HalfwidthKatakana,
+                        );
+                        ui.separator();
+                        intp_button(
+                            intp,
+                            intp_idx,
+                            ui,
+                            "Ａ",
+                            "Full-width (ctrl+')",
+                            Intp::Fullwidth,
+                        );
                     });
                     ui.separator();
-                    let hiragana = romaji_to_kana(seg, &HIRAGANA);
-                    let hiragana = hiragana.trim();
-                    let katakana = romaji_to_kana(seg, &KATAKANA);
-                    let katakana = katakana.trim();
-                    gen_dict_ui_for_hiragana(ui, intp, intp_idx, cached_suggestions, sel_changed);
+                    if loading {
+                        ui.spinner();
+                    }
+                    gen_dict_ui_for_hiragana(
+                        ui,
+                        intp,
+                        intp_idx,
+                        cached_suggestions,
+                        kanji_db,
+                        sel_changed,
+                    );
+                    completion_ui(
+                        ui,
+                        cached_suggestions,
+                        selected_completion,
+                        sel_changed,
+                        &mut clicked_completion,
+                    );
                     for rad in crate::radicals::by_name(hiragana) {
                         for ch in rad.chars {
                             if ui
@@ -456,22 +648,52 @@ fn suggestion_ui_strip(
             strip.cell(|ui| {
                 ui.separator();
                 if let Some(Intp::Dictionary {
-                    en,
+                    entry,
                     root,
                     kanji_idx,
-                    ..
                 }) = intp.get_mut(&intp_idx)
+                    && let Some(en) = entry.entry()
                 {
-                    dict_en_ui_scroll(ui, en, root.as_ref(), Some(kanji_idx));
+                    dict_en_ui_scroll(ui, &en, root.as_ref(), Some(kanji_idx), kanji_db);
                 } else {
                     egui::ScrollArea::vertical().show(ui, |ui| {
                         for sugg in &cached_suggestions.jmdict {
-                            dict_en_ui(ui, &sugg.entry, sugg.mugo_root.as_ref(), None);
+                            dict_en_ui(ui, &sugg.entry, sugg.mugo_root.as_ref(), None, kanji_db);
                         }
                     });
                 }
             });
         });
+    clicked_completion
+}
+
+/// Words that start with what's typed so far, clicking one sets the index of it
+fn completion_ui(
+    ui: &mut egui::Ui,
+    suggestions: &CachedSuggestions,
+    selected_completion: Option<usize>,
+    sel_changed: bool,
+    clicked: &mut Option<usize>,
+) {
+    if suggestions.completions.is_empty() {
+        return;
+    }
+    ui.separator();
+    ui.label("Completions (ctrl+space)");
+    for (i, completion) in suggestions.completions.iter().enumerate() {
+        let text = match completion.entry.kanji_elements().next() {
+            Some(kanji) => format!("{} ({})", kanji.text, completion.reading),
+            None => completion.reading.to_owned(),
+        };
+        let selected = selected_completion == Some(i);
+        let re = ui.selectable_label(selected, text);
+        if selected && sel_changed {
+            re.scroll_to_me(Some(egui::Align::Center));
+        }
+        if re.clicked() {
+            *clicked = Some(i);
+        }
+    }
 }
 
 fn intp_button(
@@ -514,28 +736,41 @@ fn gen_dict_ui_for_hiragana(
     intp: &mut IntpMap,
     intp_idx: usize,
     suggestions: &CachedSuggestions,
+    kanji_db: &KanjiDb,
     sel_changed: bool,
 ) {
-    for (si, suggestion) in suggestions.jmdict.iter().enumerate() {
+    for suggestion in &suggestions.jmdict {
         // Same entry, different kanji goes into horizontal layout
         let Some(kanji_str) = suggestion.entry.kanji_elements().map(|e| e.text).next() else {
             continue;
         };
         let hover_ui = |ui: &mut egui::Ui| {
             ui.set_max_width(400.0);
-            dict_en_ui_scroll(ui, &suggestion.entry, suggestion.mugo_root.as_ref(), None);
+            if let Some(reading) = suggestion.correction {
+                ui.label(format!("Possible typo, did you mean {reading}?"));
+                ui.separator();
+            }
+            dict_en_ui_scroll(
+                ui,
+                &suggestion.entry,
+                suggestion.mugo_root.as_ref(),
+                None,
+                kanji_db,
+            );
         };
         let mut scroll = false;
         let mut selected = false;
-        if let Some(Intp::Dictionary { cached_sug_idx, .. }) = intp.get(&intp_idx)
-            && *cached_sug_idx == si
+        if let Some(Intp::Dictionary { entry, .. }) = intp.get(&intp_idx)
+            && *entry == suggestion.id()
         {
             selected = true;
             scroll = true;
         }
-        let re = ui
-            .selectable_label(selected, kanji_str)
-            .on_hover_ui(hover_ui);
+        let text = match suggestion.correction {
+            Some(_) => egui::RichText::new(format!("✏ {kanji_str}")).italics(),
+            None => egui::RichText::new(kanji_str),
+        };
+        let re = ui.selectable_label(selected, text).on_hover_ui(hover_ui);
         if scroll && sel_changed {
             re.scroll_to_me(Some(egui::Align::Center));
         }
@@ -543,8 +778,7 @@ fn gen_dict_ui_for_hiragana(
             intp.insert(
                 intp_idx,
                 Intp::Dictionary {
-                    cached_sug_idx: si,
-                    en: suggestion.entry,
+                    entry: suggestion.id(),
                     kanji_idx: 0,
                     root: suggestion.mugo_root.clone(),
                 },
@@ -2,9 +2,12 @@ use {
     crate::{
         appstate::{AppState, UiState},
         egui,
+        kana::RomanizationScheme,
         kanji::Kanji,
+        worker::Job,
     },
     ids_rust::FilterLevel,
+    std::sync::Arc,
 };
 
 #[derive(Default)]
@@ -13,6 +16,8 @@ pub struct KanjiUiState {
     tab: Tab = Tab::Kanji,
     adv_args: ids_rust::SearchArgs = default_adv_args(),
     adv_results: Vec<ids_rust::SearchResult>,
+    /// Only the latest search matters, so there's nothing to key it by
+    adv_search_job: Job<(), Vec<ids_rust::SearchResult>>,
     adv_input_buf: String,
 }
 
@@ -49,7 +54,7 @@ pub fn kanji_ui(ui: &mut egui::Ui, app: &mut AppState) {
     ui.separator();
     match app.kanji_ui_state.tab {
         Tab::Kanji => kanji_tab(ui, app),
-        Tab::Radicals => radicals_tab(ui, &app.kanji_ui_state),
+        Tab::Radicals => radicals_tab(ui, &app.kanji_ui_state, app.romanization),
         Tab::Advanced => advanced_tab(ui, app),
     }
 }
@@ -85,15 +90,15 @@ pub fn kanji_tab(ui: &mut egui::Ui, app: &mut AppState) {
     );
 }
 
-pub fn radicals_tab(ui: &mut egui::Ui, kan_ui: &KanjiUiState) {
+pub fn radicals_tab(ui: &mut egui::Ui, kan_ui: &KanjiUiState, scheme: RomanizationScheme) {
     egui::ScrollArea::vertical()
         .auto_shrink(false)
         .show(ui, |ui| {
             for (i, rad) in crate::radicals::RADICALS.iter().enumerate() {
                 let filt = &kan_ui.filter_string;
                 if !filt.is_empty() {
-                    let hir = crate::conv::romaji_to_kana(filt, &crate::kana::HIRAGANA);
-                    let kat = crate::conv::romaji_to_kana(filt, &crate::kana::KATAKANA);
+                    let hir = crate::conv::romaji_to_kana(filt, scheme.hiragana());
+                    let kat = crate::conv::romaji_to_kana(filt, scheme.katakana());
                     if !rad
                         .common_names
                         .iter()
@@ -154,11 +159,20 @@ pub fn advanced_tab(ui: &mut egui::Ui, app: &mut AppState) {
                     .selectable_value(&mut args.filter_level, FilterLevel::Media, "Media")
                     .clicked();
             });
+        if app.kanji_ui_state.adv_search_job.is_running() {
+            ui.spinner();
+        }
     });
     if any_changed {
         args.input = (!app.kanji_ui_state.adv_input_buf.is_empty())
             .then(|| app.kanji_ui_state.adv_input_buf.clone());
-        app.kanji_ui_state.adv_results = app.ids_kanji_data.search(args.clone());
+        let args = args.clone();
+        let data = Arc::clone(&app.ids_kanji_data);
+        app.kanji_ui_state
+            .adv_search_job
+            .start((), ui.ctx(), move |_| data.search(args));
+    } else if let Some(results) = app.kanji_ui_state.adv_search_job.poll(&()) {
+        app.kanji_ui_state.adv_results = results;
     }
     ui.separator();
     let mut prev_strokes = 0;
@@ -0,0 +1,69 @@
+use crate::{
+    appstate::{AppState, UiState},
+    egui,
+};
+
+pub fn learned_ui(ui: &mut egui::Ui, app: &mut AppState) {
+    ui.horizontal(|ui| {
+        if ui.link("Back (Esc)").clicked() || ui.input(|inp| inp.key_pressed(egui::Key::Escape)) {
+            app.ui_state = UiState::Input;
+        }
+        ui.separator();
+        if ui
+            .add_enabled(
+                !app.learned.map.is_empty(),
+                egui::Button::new("🗑 Forget all"),
+            )
+            .clicked()
+        {
+            app.learned.map.clear();
+            save(app);
+        }
+    });
+    ui.separator();
+    if app.learned.map.is_empty() {
+        ui.label("Nothing learned yet. Words picked from the suggestions are learned on copy.");
+        return;
+    }
+    let mut forget = None;
+    egui::ScrollArea::vertical()
+        .auto_shrink(false)
+        .show(ui, |ui| {
+            egui::Grid::new("learned_grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Reading");
+                    ui.strong("Word");
+                    ui.strong("Conjugation");
+                    ui.strong("Times picked");
+                    ui.end_row();
+                    for (key, choices) in &app.learned.map {
+                        for choice in choices {
+                            let word = choice.entry.entry().and_then(|en| {
+                                en.kanji_elements()
+                                    .nth(choice.kanji_idx)
+                                    .map(|elem| elem.text)
+                            });
+                            ui.label(&key.0);
+                            ui.label(word.unwrap_or("<unknown entry>"));
+                            ui.label(key.1.as_deref().unwrap_or("-"));
+                            ui.label(choice.count.to_string());
+                            if ui.button("Forget").clicked() {
+                                forget = Some((key.clone(), choice.entry, choice.kanji_idx));
+                            }
+                            ui.end_row();
+                        }
+                    }
+                });
+        });
+    if let Some((key, entry, kanji_idx)) = forget {
+        app.learned.forget(&key, entry, kanji_idx);
+        save(app);
+    }
+}
+
+fn save(app: &AppState) {
+    if let Err(e) = app.learned.save() {
+        eprintln!("Failed to save learned choices: {e}");
+    }
+}
@@ -0,0 +1,178 @@
+//! Undo for the romaji text and the interpretations together
+
+use crate::conv::{Intp, IntpMap};
+
+/// Text edits less than this many seconds apart are undone in one step
+const TYPING_MERGE_TIME: f64 = 1.0;
+/// Maximum number of undo steps kept
+const MAX_STEPS: usize = 200;
+
+#[derive(Clone, Default)]
+pub struct UndoState {
+    pub romaji_buf: String,
+    pub intp: IntpMap,
+}
+
+#[derive(Default)]
+pub struct UndoStack {
+    undo: Vec<UndoState>,
+    redo: Vec<UndoState>,
+    /// The state as of the last update, which the next change is undone to
+    current: UndoState,
+    /// When the text was last edited, if the last change was an edit of the text
+    last_typing: Option<f64>,
+}
+
+impl UndoStack {
+    /// Records an undo step if the text or the interpretations changed since the last update
+    ///
+    /// Text edits are assumed to be typing, and merged with earlier typing.
+    pub fn update(&mut self, romaji_buf: &str, intp: &IntpMap, time: f64) {
+        self.record(romaji_buf, intp, Some(time));
+    }
+    /// Call before changing the text in some other way than typing (replacing all of it, etc.),
+    /// with the state from before the change
+    ///
+    /// This ends the typing step, so the change gets undone on its own.
+    pub fn break_merge(&mut self, romaji_buf: &str, intp: &IntpMap) {
+        self.record(romaji_buf, intp, None);
+    }
+    /// `typing_time` is when the text was typed, `None` if it wasn't typed
+    fn record(&mut self, romaji_buf: &str, intp: &IntpMap, typing_time: Option<f64>) {
+        let text_changed = self.current.romaji_buf != romaji_buf;
+        if !text_changed && same_intp_map(&self.current.intp, intp) {
+            self.last_typing = self.last_typing.filter(|_| typing_time.is_some());
+            return;
+        }
+        let merge = text_changed
+            && typing_time
+                .zip(self.last_typing)
+                .is_some_and(|(time, last)| time - last < TYPING_MERGE_TIME);
+        let new = UndoState {
+            romaji_buf: romaji_buf.to_owned(),
+            intp: intp.clone(),
+        };
+        let prev = std::mem::replace(&mut self.current, new);
+        if !merge {
+            self.undo.push(prev);
+            if self.undo.len() > MAX_STEPS {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+        self.last_typing = typing_time.filter(|_| text_changed);
+    }
+    /// Steps back, returning the state to restore
+    pub fn undo(&mut self) -> Option<UndoState> {
+        let state = self.undo.pop()?;
+        self.redo.push(std::mem::replace(&mut self.current, state));
+        self.last_typing = None;
+        Some(self.current.clone())
+    }
+    /// Steps forward again after an undo, returning the state to restore
+    pub fn redo(&mut self) -> Option<UndoState> {
+        let state = self.redo.pop()?;
+        self.undo.push(std::mem::replace(&mut self.current, state));
+        self.last_typing = None;
+        Some(self.current.clone())
+    }
+}
+
+fn same_intp_map(a: &IntpMap, b: &IntpMap) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .all(|(i, a)| b.get(i).is_some_and(|b| same_intp(a, b)))
+}
+
+/// Whether two interpretations are the same choice (`Intp` can't derive `PartialEq`)
+fn same_intp(a: &Intp, b: &Intp) -> bool {
+    match (a, b) {
+        (
+            Intp::Dictionary {
+                entry: a_entry,
+                kanji_idx: a_kanji,
+                root: a_root,
+            },
+            Intp::Dictionary {
+                entry: b_entry,
+                kanji_idx: b_kanji,
+                root: b_root,
+            },
+        ) => a_entry == b_entry && a_kanji == b_kanji && same_root(a_root, b_root),
+        (Intp::Kanji { db_idx: a }, Intp::Kanji { db_idx: b }) => a == b,
+        (Intp::Radical(a), Intp::Radical(b)) => a.chars == b.chars,
+        (Intp::Custom(a), Intp::Custom(b)) => a == b,
+        _ => std::mem::discriminant(a) == std::mem::discriminant(b),
+    }
+}
+
+fn same_root(a: &Option<mugo::Root>, b: &Option<mugo::Root>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.text == b.text && a.kind == b.kind && a.steps == b.steps,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn test_same_intp_root() {
+    let dict = |kind| Intp::Dictionary {
+        entry: crate::jmdict_index::EntryId(1),
+        kanji_idx: 0,
+        root: Some(mugo::Root {
+            text: "か".to_owned(),
+            kind,
+            steps: Vec::new(),
+        }),
+    };
+    assert!(same_intp(
+        &dict(mugo::RootKind::Ichidan),
+        &dict(mugo::RootKind::Ichidan)
+    ));
+    assert!(!same_intp(
+        &dict(mugo::RootKind::Ichidan),
+        &dict(mugo::RootKind::GodanKu)
+    ));
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut stack = UndoStack::default();
+    let katakana = IntpMap::from([(0, Intp::Katakana)]);
+    // Typing in quick succession is one step
+    stack.update("ka", &IntpMap::new(), 0.0);
+    stack.update("kana", &IntpMap::new(), 0.5);
+    stack.update("kana", &katakana, 5.0);
+    stack.update("kana", &katakana, 6.0);
+    stack.update("kana", &IntpMap::new(), 7.0);
+    let state = stack.undo().unwrap();
+    assert_eq!(state.romaji_buf, "kana");
+    assert!(matches!(state.intp.get(&0), Some(Intp::Katakana)));
+    let state = stack.undo().unwrap();
+    assert_eq!(state.romaji_buf, "kana");
+    assert!(state.intp.is_empty());
+    let state = stack.undo().unwrap();
+    assert_eq!(state.romaji_buf, "");
+    assert!(stack.undo().is_none());
+    assert_eq!(stack.redo().unwrap().romaji_buf, "kana");
+    assert!(matches!(
+        stack.redo().unwrap().intp.get(&0),
+        Some(Intp::Katakana)
+    ));
+    // A new change discards what could be redone
+    stack.update("kanaa", &katakana, 8.0);
+    assert!(stack.redo().is_none());
+}
+
+#[test]
+fn test_undo_replacement_after_typing() {
+    let mut stack = UndoStack::default();
+    stack.update("ko", &IntpMap::new(), 0.0);
+    stack.update("kore", &IntpMap::new(), 0.2);
+    // The text gets replaced right after typing (copied and cleared)
+    stack.break_merge("kore", &IntpMap::new());
+    stack.update("", &IntpMap::new(), 0.4);
+    assert_eq!(stack.undo().unwrap().romaji_buf, "kore");
+    assert_eq!(stack.undo().unwrap().romaji_buf, "");
+    assert!(stack.undo().is_none());
+}
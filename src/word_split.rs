@@ -0,0 +1,185 @@
+//! Automatic splitting of unspaced romaji into dictionary words
+
+use {
+    crate::{
+        conv::romaji_atoms,
+        kana::RomanizationScheme,
+        segment::{InputSpan, WORD_JOINER},
+        worker::CancelFlag,
+    },
+    std::{collections::HashMap, ops::Range},
+};
+
+/// Longest word that's looked up, in romaji atoms
+const MAX_WORD_ATOMS: usize = 12;
+
+/// A word to split, and the scheme it's typed in
+pub type SplitKey = (RomanizationScheme, String);
+
+/// Split points of already split words, keyed by the word
+pub type SplitCache = HashMap<SplitKey, Vec<usize>>;
+
+/// Splits romaji words in `segments` into dictionary words, where they can be found
+///
+/// Words joined with [`WORD_JOINER`] are left alone.
+/// Words that aren't in the cache yet are left alone too, and returned along with the
+/// segments, so they can be looked up with [`find_split_points`].
+pub fn split_words(
+    text: &str,
+    segments: &[InputSpan],
+    scheme: RomanizationScheme,
+    cache: &SplitCache,
+) -> (Vec<InputSpan>, Vec<SplitKey>) {
+    let mut out = Vec::with_capacity(segments.len());
+    let mut uncached = Vec::new();
+    for &span in segments {
+        let InputSpan::RomajiWord { start, end } = span else {
+            out.push(span);
+            continue;
+        };
+        let word = &text[start..end];
+        if word.contains(WORD_JOINER) {
+            out.push(span);
+            continue;
+        }
+        let key = (scheme, word.to_owned());
+        let Some(points) = cache.get(&key) else {
+            uncached.push(key);
+            out.push(span);
+            continue;
+        };
+        let mut word_start = start;
+        for &point in points.iter() {
+            out.push(InputSpan::RomajiWord {
+                start: word_start,
+                end: start + point,
+            });
+            word_start = start + point;
+        }
+        out.push(InputSpan::RomajiWord {
+            start: word_start,
+            end,
+        });
+    }
+    (out, uncached)
+}
+
+/// Finds where to split the words, for the cache. Stops early if cancelled.
+///
+/// This looks up a lot of words in the dictionary, so it's done in the background.
+pub fn find_split_points(words: &[SplitKey], cancel: &CancelFlag) -> Vec<(SplitKey, Vec<usize>)> {
+    words
+        .iter()
+        .take_while(|_| !cancel.is_cancelled())
+        .map(|(scheme, word)| {
+            let atoms = romaji_atoms(word, scheme.hiragana());
+            let points = split_points(&atoms, is_dictionary_word);
+            ((*scheme, word.clone()), points)
+        })
+        .collect()
+}
+
+/// Whether `kana` is the reading of a dictionary word, possibly conjugated
+fn is_dictionary_word(kana: &str) -> bool {
+    let index = crate::jmdict_index::get();
+    if index.contains(kana) {
+        return true;
+    }
+    mugo::deconjugate(kana)
+        .iter()
+        .any(|root| !index.lookup_conj(root).is_empty())
+}
+
+/// Finds the best way to split the atoms into words, returns the byte offsets of the splits
+///
+/// Longer words are preferred (scored by the square of their length).
+/// Stretches of atoms that aren't part of any word are kept together.
+fn split_points(atoms: &[(Range<usize>, &str)], is_word: impl Fn(&str) -> bool) -> Vec<usize> {
+    // (score, start atom of the last piece, whether the last piece is a word)
+    let mut best: Vec<Option<(usize, usize, bool)>> = vec![None; atoms.len() + 1];
+    best[0] = Some((0, 0, false));
+    for end in 1..=atoms.len() {
+        for start in end.saturating_sub(MAX_WORD_ATOMS)..end {
+            let Some((base, ..)) = best[start] else {
+                continue;
+            };
+            let kana: String = atoms[start..end].iter().map(|(_, kana)| *kana).collect();
+            let candidate = if is_word(&kana) {
+                let len = kana.chars().count();
+                (base + len * len, start, true)
+            } else if end - start == 1 {
+                (base, start, false)
+            } else {
+                continue;
+            };
+            if best[end].is_none_or(|(score, ..)| candidate.0 > score) {
+                best[end] = Some(candidate);
+            }
+        }
+    }
+    // Walk back through the best pieces, merging adjacent non-words
+    let mut points = Vec::new();
+    let mut end = atoms.len();
+    let mut prev_is_word = true;
+    while end > 0 {
+        let Some((_, start, is_word)) = best[end] else {
+            break;
+        };
+        if (is_word || prev_is_word) && end != atoms.len() {
+            points.push(atoms[end].0.start);
+        }
+        prev_is_word = is_word;
+        end = start;
+    }
+    points.reverse();
+    points
+}
+
+#[test]
+fn test_split_points() {
+    let words = [
+        "わたし",
+        "は",
+        "がくせい",
+        "です",
+        "がく",
+        "せい",
+        "た",
+        "し",
+    ];
+    let split = |romaji| {
+        let atoms = romaji_atoms(romaji, RomanizationScheme::Hepburn.hiragana());
+        let points = split_points(&atoms, |kana| words.contains(&kana));
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for point in points {
+            pieces.push(&romaji[start..point]);
+            start = point;
+        }
+        pieces.push(&romaji[start..]);
+        pieces
+    };
+    assert_eq!(
+        split("watashihagakuseidesu"),
+        ["watashi", "ha", "gakusei", "desu"]
+    );
+    assert_eq!(split("gakuseixyzdesu"), ["gakusei", "xyz", "desu"]);
+    assert_eq!(split("xyz"), ["xyz"]);
+    assert_eq!(split("gakkou"), ["gakkou"]);
+}
+
+#[test]
+fn test_split_words() {
+    let text = "watashihagakuseidesu ne";
+    let segments = crate::segment::segment(text);
+    let scheme = RomanizationScheme::Hepburn;
+    let mut cache = SplitCache::default();
+    // Not split before the split points are found
+    let (split, uncached) = split_words(text, &segments, scheme, &cache);
+    assert_eq!(split.len(), 2);
+    assert_eq!(uncached.len(), 2);
+    cache.extend(find_split_points(&uncached, &CancelFlag::default()));
+    let (split, uncached) = split_words(text, &segments, scheme, &cache);
+    assert_eq!(split.len(), 5);
+    assert!(uncached.is_empty());
+}
@@ -0,0 +1,133 @@
+//! Slow lookups running off the UI thread, so typing never stalls
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc::{Receiver, TryRecvError},
+};
+
+/// Tells a running computation that its result isn't wanted anymore
+#[derive(Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    /// Long computations should check this regularly, and stop early if it's set
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A computation running in the background, replaced by every new request
+///
+/// Results are keyed by what they were requested for, so a late result never gets used for
+/// something else.
+pub struct Job<K, T> {
+    key: Option<K>,
+    cancel: CancelFlag,
+    rx: Option<Receiver<T>>,
+}
+
+impl<K, T> Default for Job<K, T> {
+    fn default() -> Self {
+        Self {
+            key: None,
+            cancel: CancelFlag::default(),
+            rx: None,
+        }
+    }
+}
+
+impl<K: PartialEq, T: Send + 'static> Job<K, T> {
+    /// Starts computing the result for `key`, cancelling the previous computation
+    ///
+    /// `ctx` gets a repaint request once the result is ready, so it gets polled.
+    pub fn start(
+        &mut self,
+        key: K,
+        ctx: &egui::Context,
+        f: impl FnOnce(&CancelFlag) -> T + Send + 'static,
+    ) {
+        self.cancel();
+        let cancel = CancelFlag::default();
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.key = Some(key);
+        self.cancel = cancel.clone();
+        self.rx = Some(rx);
+        let ctx = ctx.clone();
+        let run = move || {
+            let result = f(&cancel);
+            // The job might have been dropped in the meantime, that's fine
+            if !cancel.is_cancelled() && tx.send(result).is_ok() {
+                ctx.request_repaint();
+            }
+        };
+        // No threads on the web, so it's computed right away there
+        #[cfg(target_arch = "wasm32")]
+        run();
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(run);
+    }
+    /// Cancels the running computation, if any
+    pub fn cancel(&mut self) {
+        self.cancel.cancel();
+        self.key = None;
+        self.rx = None;
+    }
+    /// Takes the result if it has arrived, and it's for `key`
+    pub fn poll(&mut self, key: &K) -> Option<T> {
+        if self.key.as_ref() != Some(key) {
+            return None;
+        }
+        match self.rx.as_ref()?.try_recv() {
+            Ok(result) => {
+                self.rx = None;
+                Some(result)
+            }
+            Err(TryRecvError::Empty) => None,
+            // The computation panicked, don't wait for it forever
+            Err(TryRecvError::Disconnected) => {
+                self.rx = None;
+                None
+            }
+        }
+    }
+    /// Whether the running computation, or the last one, is for `key`
+    pub fn is_for(&self, key: &K) -> bool {
+        self.key.as_ref() == Some(key)
+    }
+    /// Whether a result is still being waited for
+    pub fn is_running(&self) -> bool {
+        self.rx.is_some()
+    }
+}
+
+#[test]
+fn test_job() {
+    let wait = |job: &mut Job<&str, usize>, key| loop {
+        if let Some(result) = job.poll(&key) {
+            return Some(result);
+        }
+        if !job.is_running() {
+            return None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    };
+    let ctx = egui::Context::default();
+    let mut job = Job::default();
+    job.start("a", &ctx, |_| 1);
+    assert_eq!(job.poll(&"b"), None);
+    assert_eq!(wait(&mut job, "a"), Some(1));
+    assert!(!job.is_running());
+    // A stale computation gets cancelled and its result is never seen
+    job.start("b", &ctx, |cancel| {
+        while !cancel.is_cancelled() {
+            std::thread::yield_now();
+        }
+        2
+    });
+    job.start("c", &ctx, |_| 3);
+    assert_eq!(wait(&mut job, "c"), Some(3));
+}